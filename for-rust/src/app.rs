@@ -11,7 +11,7 @@ impl VelopackApp {
     /// Runs the Velopack startup logic. This should be the first thing to run in your app.
     /// In some circumstances it may terminate/restart the process to perform tasks.
     pub fn run(&self) {
-        for (_, arg) in std::env::args().enumerate() {
+        for arg in std::env::args() {
             match arg.to_ascii_lowercase().as_str() {
                 "--veloapp-install" => exit(0),
                 "--veloapp-updated" => exit(0),
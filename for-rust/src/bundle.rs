@@ -22,10 +22,10 @@ pub struct BundleZip<'a> {
 pub fn load_bundle_from_file<'a, P: AsRef<Path>>(file_name: P) -> Result<BundleZip<'a>> {
     let file_name = file_name.as_ref();
     debug!("Loading bundle from file '{}'...", file_name.to_string_lossy());
-    let file = util::retry_io(|| File::open(&file_name))?;
+    let file = util::retry_io(|| File::open(file_name))?;
     let cursor: Box<dyn ReadSeek> = Box::new(file);
     let zip = ZipArchive::new(cursor)?;
-    return Ok(BundleZip { zip: Rc::new(RefCell::new(zip)) });
+    Ok(BundleZip { zip: Rc::new(RefCell::new(zip)) })
 }
 
 #[allow(dead_code)]
@@ -36,9 +36,7 @@ impl BundleZip<'_> {
         let mut archive = self.zip.borrow_mut();
 
         for i in 0..archive.len() {
-            let file = archive.by_index(i);
-            if file.is_ok() {
-                let file = file.unwrap();
+            if let Ok(file) = archive.by_index(i) {
                 total_uncompressed_size += file.size();
                 total_compressed_size += file.compressed_size();
             }
@@ -61,13 +59,12 @@ impl BundleZip<'_> {
             return None;
         }
 
-        let res: Result<Vec<u8>, _> = sf.unwrap().bytes().collect();
-        if res.is_err() {
+        let mut bytes = Vec::new();
+        if sf.unwrap().read_to_end(&mut bytes).is_err() {
             warn!("Could not find splash image in bundle.");
             return None;
         }
 
-        let bytes = res.unwrap();
         if bytes.is_empty() {
             warn!("Could not find splash image in bundle.");
             return None;
@@ -1,60 +1,1378 @@
-use crate::util;
+use crate::{hash, util, VelopackError};
 use anyhow::Result;
+use base64::Engine;
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{self, Read, Seek, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, Condvar, LazyLock, Mutex};
+use std::time::Duration;
 
-pub fn download_url_to_file<A>(url: &str, file_path: &str, mut progress: A) -> Result<()>
+/// The number of simultaneous connections allowed to a single host when `DownloadOptions::max_connections_per_host` is not set.
+pub const DEFAULT_MAX_CONNECTIONS_PER_HOST: usize = 4;
+
+/// The number of redirects which will be followed for a single request when `DownloadOptions::max_redirects` is not set.
+pub const DEFAULT_MAX_REDIRECTS: u32 = 5;
+
+/// The number of times a resumable download is resumed after the connection is reset, when
+/// `DownloadOptions::connection_reset_retries` is not set.
+pub const DEFAULT_CONNECTION_RESET_RETRIES: u32 = 3;
+
+/// The largest release feed which is read into memory when `DownloadOptions::max_feed_size` is not set.
+pub const DEFAULT_MAX_FEED_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Reported to a progress callback instead of a percentage when the server does not declare the size of the download (eg. a
+/// chunked response without `Content-Length`), so callers can show an indeterminate indicator rather than a progress bar.
+/// 100 is still reported once the download completes.
+pub const INDETERMINATE_PROGRESS: i16 = -1;
+
+/// The delays before each retry of a request which failed because the host name could not be resolved. DNS failures are
+/// common (and usually brief) while a network is coming back up, eg. after a laptop resumes from sleep.
+const DNS_RETRY_DELAYS: [Duration; 3] = [Duration::from_millis(100), Duration::from_millis(250), Duration::from_millis(500)];
+
+/// Resolves a 'host:port' string to the socket addresses to connect to.
+pub type HostResolver = Arc<dyn Fn(&str) -> io::Result<Vec<SocketAddr>> + Send + Sync>;
+
+/// Which addresses of a dual-stack host are connected to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IpStack {
+    /// Connect to each address in the order it was resolved, as the operating system would.
+    #[default]
+    System,
+    /// Only connect to IPv4 addresses, eg. for troubleshooting a broken IPv6 network.
+    Ipv4Only,
+    /// Only connect to IPv6 addresses.
+    Ipv6Only,
+}
+
+impl IpStack {
+    /// Filters and orders the resolved addresses of a host according to this strategy.
+    fn select_addresses(self, netloc: &str, addrs: Vec<SocketAddr>) -> io::Result<Vec<SocketAddr>> {
+        let addrs: Vec<SocketAddr> = match self {
+            IpStack::System => return Ok(addrs),
+            IpStack::Ipv4Only => addrs.into_iter().filter(|a| a.is_ipv4()).collect(),
+            IpStack::Ipv6Only => addrs.into_iter().filter(|a| a.is_ipv6()).collect(),
+        };
+        if addrs.is_empty() {
+            let msg = format!("'{}' has no addresses for the selected IP stack ({:?}).", netloc, self);
+            return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, msg));
+        }
+        Ok(addrs)
+    }
+}
+
+/// Settings which control how the download client connects to a remote host.
+#[derive(Clone, Default)]
+pub struct DownloadOptions {
+    /// Base64 encoded SHA-256 hashes of the SubjectPublicKeyInfo of the certificates which are accepted in the server chain.
+    /// When this is empty, any certificate which is trusted by the platform will be accepted.
+    pub pinned_public_keys: Vec<String>,
+    /// Additional root certificates to trust, on top of the platform's default trust roots.
+    pub root_certificates: Vec<native_tls::Certificate>,
+    /// The maximum number of requests which may be in progress to the same host at once, across every download in the
+    /// process. Further requests wait until a connection is released. Defaults to `DEFAULT_MAX_CONNECTIONS_PER_HOST`.
+    pub max_connections_per_host: Option<usize>,
+    /// Cookies to send with every request to a matching host (eg. an SSO session cookie). When any are set, redirects
+    /// are followed by the download client itself, so that cookies set by the server along a redirect chain are kept.
+    pub cookies: Vec<Cookie>,
+    /// If a partially downloaded file already exists at the destination, request only the remaining bytes instead of
+    /// starting again. Partial files are kept (rather than removed) when a download fails, so it can be resumed later.
+    pub resume_partial_downloads: bool,
+    /// When `resume_partial_downloads` is set, the number of times a download is resumed after the connection is reset or
+    /// aborted part way through (eg. by a proxy on a long transfer). Defaults to `DEFAULT_CONNECTION_RESET_RETRIES`.
+    pub connection_reset_retries: Option<u32>,
+    /// The maximum number of redirects to follow for a single request. Defaults to `DEFAULT_MAX_REDIRECTS`. Only `3xx`
+    /// responses with a `Location` header are followed; redirects in the body (eg. an HTML meta-refresh) never are.
+    pub max_redirects: Option<u32>,
+    /// If this is not empty, redirects are only followed to these hosts (or the host of the original request). A redirect
+    /// anywhere else fails the request, rather than downloading content from a location controlled by someone else.
+    pub allowed_redirect_hosts: Vec<String>,
+    /// Downloads files of at least `parallel_download_threshold` bytes as this many byte ranges in parallel. Values of 0 or 1
+    /// disable parallel downloads, and servers which do not support range requests are downloaded as a single stream.
+    pub parallel_download_parts: usize,
+    /// The smallest file which is downloaded in parallel parts, when `parallel_download_parts` is set.
+    pub parallel_download_threshold: u64,
+    /// Replaces the system DNS resolver (eg. to pin a host to a known address, or to resolve through a custom service).
+    pub resolver: Option<HostResolver>,
+    /// Which addresses of a dual-stack host are connected to. Defaults to `IpStack::System`.
+    pub ip_stack: IpStack,
+    /// Additional headers to send with every request (eg. an API key). When any are set, redirects are followed by the
+    /// download client itself, so that sensitive headers can be dropped when a redirect leads to another host.
+    pub headers: Vec<RequestHeader>,
+    /// **DANGER: for development only.** Accepts any server certificate, including self-signed, expired, or untrusted
+    /// certificates, which allows anyone able to intercept the connection to serve malicious updates. Pinned public keys
+    /// are still checked. Defaults to false, and should never be enabled in a released app.
+    pub danger_accept_invalid_certs: bool,
+    /// Skips verifying downloads against the checksum headers sent by object stores (`x-amz-checksum-sha256` by S3, and
+    /// `Content-MD5` or `x-ms-blob-content-md5` by Azure). By default these are verified whenever they are present, and a
+    /// download in parallel parts is verified once it has been assembled.
+    pub ignore_checksum_headers: bool,
+    /// Overwrites partially downloaded files with zeros before removing them after a failed download. This is best-effort,
+    /// see `UpdateOptions::SecureDelete`.
+    pub secure_delete: bool,
+    /// Limits the number of package bytes downloaded, see `DownloadQuota`.
+    pub quota: Option<DownloadQuota>,
+    /// The largest release feed (or other file read into memory, such as a feed pointer) which will be downloaded. Larger
+    /// responses fail with `VelopackError::FeedTooLarge` rather than exhausting memory. Defaults to `DEFAULT_MAX_FEED_SIZE`.
+    pub max_feed_size: Option<u64>,
+}
+
+impl DownloadOptions {
+    fn follows_redirects_manually(&self) -> bool {
+        !self.cookies.is_empty() || !self.allowed_redirect_hosts.is_empty() || !self.headers.is_empty()
+    }
+
+    /// Checks these options for mistakes which can be found without connecting anywhere, adding a description of each
+    /// one to `problems`.
+    pub fn validate(&self, problems: &mut Vec<String>) {
+        for pin in &self.pinned_public_keys {
+            let decoded = base64::engine::general_purpose::STANDARD.decode(pin.trim_start_matches("sha256/"));
+            if decoded.map(|d| d.len() != 32).unwrap_or(true) {
+                problems.push(format!("Pinned public key '{}' is not a base64 encoded SHA-256 hash.", pin));
+            }
+        }
+        if self.max_connections_per_host == Some(0) {
+            problems.push("The maximum connections per host must be at least 1.".to_owned());
+        }
+        for cookie in &self.cookies {
+            if cookie.name.trim().is_empty() || cookie.domain.trim().is_empty() {
+                problems.push(format!("Cookie '{}' for domain '{}' must have both a name and a domain.", cookie.name, cookie.domain));
+            }
+        }
+        for header in &self.headers {
+            if header.name.trim().is_empty() || header.name.contains([':', '\r', '\n']) || header.value.contains(['\r', '\n']) {
+                problems.push(format!("The request header '{}' is not a valid header name and value.", header.name));
+            }
+        }
+        if self.allowed_redirect_hosts.iter().any(|h| h.trim().is_empty()) {
+            problems.push("Allowed redirect hosts must not be empty.".to_owned());
+        }
+    }
+}
+
+/// Checks that the url is an absolute http or https url with a host, adding a description of the problem to `problems` if not.
+pub fn validate_http_url(url: &str, problems: &mut Vec<String>) {
+    match url::Url::parse(url) {
+        Ok(parsed) if parsed.scheme() != "http" && parsed.scheme() != "https" => {
+            problems.push(format!("The url '{}' must use http or https, not '{}'.", util::sanitize_url(url), parsed.scheme()))
+        }
+        Ok(parsed) if parsed.host_str().unwrap_or_default().is_empty() => {
+            problems.push(format!("The url '{}' does not have a host.", util::sanitize_url(url)))
+        }
+        Ok(_) => {}
+        Err(e) => problems.push(format!("The url '{}' is not valid: {}", util::sanitize_url(url), e)),
+    }
+}
+
+/// A limit on the number of package bytes downloaded (eg. per session or per day, for users on a metered connection). The
+/// quota is a handle, so clones (eg. given to several sources) share the same count. Once it is used up, further downloads
+/// fail with `VelopackError::QuotaExceeded`. Only bytes actually received are counted, so resuming a partial download only
+/// uses the remaining bytes, and the parts of a parallel download which fails (and is retried) are not counted. Release
+/// feeds are not counted, so checking for updates keeps working.
+#[derive(Debug, Clone)]
+pub struct DownloadQuota {
+    limit: u64,
+    used: Arc<AtomicU64>,
+}
+
+impl DownloadQuota {
+    /// Create a new quota allowing up to `limit` bytes to be downloaded.
+    pub fn new(limit: u64) -> DownloadQuota {
+        DownloadQuota { limit, used: Arc::new(AtomicU64::new(0)) }
+    }
+
+    /// The number of bytes allowed by this quota.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// The number of bytes downloaded against this quota so far.
+    pub fn used(&self) -> u64 {
+        self.used.load(AtomicOrdering::SeqCst)
+    }
+
+    /// The number of bytes which can still be downloaded.
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.used())
+    }
+
+    /// Resets the count of downloaded bytes to zero (eg. at the start of a new day).
+    pub fn reset(&self) {
+        self.used.store(0, AtomicOrdering::SeqCst);
+    }
+
+    fn exceeded(&self) -> anyhow::Error {
+        error!("Download quota of {} bytes has been used ({} bytes downloaded).", self.limit, self.used());
+        VelopackError::QuotaExceeded { limit: self.limit, used: self.used() }.into()
+    }
+
+    /// Returns an error without counting anything if `bytes` more would exceed the quota.
+    fn check(&self, bytes: u64) -> Result<()> {
+        if bytes > self.remaining() {
+            return Err(self.exceeded());
+        }
+        Ok(())
+    }
+
+    /// Stops counting `bytes` which were received but then discarded (eg. the parts of a failed parallel download).
+    fn refund(&self, bytes: u64) {
+        let _ = self.used.fetch_update(AtomicOrdering::SeqCst, AtomicOrdering::SeqCst, |used| Some(used.saturating_sub(bytes)));
+    }
+
+    /// Counts `bytes` against the quota, or returns an error (without counting them) if that would exceed it.
+    fn consume(&self, bytes: u64) -> Result<()> {
+        match self
+            .used
+            .fetch_update(AtomicOrdering::SeqCst, AtomicOrdering::SeqCst, |used| used.checked_add(bytes).filter(|u| *u <= self.limit))
+        {
+            Ok(_) => Ok(()),
+            Err(_) => Err(self.exceeded()),
+        }
+    }
+}
+
+fn is_quota_error(err: &anyhow::Error) -> bool {
+    matches!(err.downcast_ref::<VelopackError>(), Some(VelopackError::QuotaExceeded { .. }))
+}
+
+/// A header which is sent with every request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestHeader {
+    /// The name of the header.
+    pub name: String,
+    /// The value of the header.
+    pub value: String,
+    /// Sensitive headers (eg. an API key) are only sent to the host of the original request, and are dropped if a redirect
+    /// leads to another host (eg. a CDN), so they are not leaked to it.
+    pub sensitive: bool,
+}
+
+/// A cookie which is sent to a host, and any of its subdomains.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    /// The name of the cookie.
+    pub name: String,
+    /// The value of the cookie.
+    pub value: String,
+    /// The domain the cookie is sent to (eg. 'updates.example.com').
+    pub domain: String,
+    /// Secure cookies are only sent over https.
+    pub secure: bool,
+}
+
+impl Cookie {
+    fn matches_host(&self, host: &str) -> bool {
+        let domain = self.domain.trim_start_matches('.');
+        host.eq_ignore_ascii_case(domain) || host.to_ascii_lowercase().ends_with(&format!(".{}", domain.to_ascii_lowercase()))
+    }
+
+    /// Parses a 'Set-Cookie' response header, defaulting the domain to the host which sent it. Returns None if the cookie
+    /// sets a domain which the host does not belong to (eg. a CDN in a redirect chain setting one for the update server),
+    /// or a bare top level domain, as it would then be sent to hosts which never set it.
+    fn parse_set_cookie(header: &str, host: &str) -> Option<Cookie> {
+        let mut parts = header.split(';');
+        let (name, value) = parts.next()?.split_once('=')?;
+        let mut cookie = Cookie { name: name.trim().to_owned(), value: value.trim().to_owned(), domain: host.to_owned(), secure: false };
+        for part in parts {
+            let (key, value) = part.split_once('=').unwrap_or((part, ""));
+            if key.trim().eq_ignore_ascii_case("Secure") {
+                cookie.secure = true;
+            } else if key.trim().eq_ignore_ascii_case("Domain") && !value.trim().is_empty() {
+                cookie.domain = value.trim().trim_start_matches('.').to_owned();
+            }
+        }
+        let is_ip = host.parse::<std::net::IpAddr>().is_ok() || host.starts_with('[');
+        let allowed = host.eq_ignore_ascii_case(&cookie.domain) || (!is_ip && cookie.domain.contains('.') && cookie.matches_host(host));
+        if !allowed {
+            warn!("Ignoring cookie '{}' from '{}', as it is for the domain '{}'.", cookie.name, host, cookie.domain);
+            return None;
+        }
+        Some(cookie)
+    }
+}
+
+/// Sends a request with the provided headers. If cookies or allowed redirect hosts are configured, redirects are followed
+/// here rather than by the agent, attaching the matching cookies to each request and keeping any cookies set by the server
+/// along the way, and refusing to follow a redirect to a host which is not allowed, or from https to http.
+#[allow(clippy::result_large_err)]
+fn send_request(
+    agent: &ureq::Agent,
+    method: &str,
+    url: &str,
+    options: &DownloadOptions,
+    headers: &[(&str, &str)],
+) -> Result<ureq::Response, ureq::Error> {
+    if !options.follows_redirects_manually() {
+        let request = headers.iter().fold(agent.request(method, url), |r, (k, v)| r.set(k, v));
+        return call_with_dns_retry(request);
+    }
+
+    let mut jar = options.cookies.clone();
+    let mut url = url::Url::parse(url).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let original_host = url.host_str().unwrap_or_default().to_owned();
+    let max_redirects = options.max_redirects.unwrap_or(DEFAULT_MAX_REDIRECTS);
+    for _ in 0..=max_redirects {
+        let host = url.host_str().unwrap_or_default().to_owned();
+        let mut request = headers.iter().fold(agent.request(method, url.as_str()), |r, (k, v)| r.set(k, v));
+        for header in &options.headers {
+            if header.sensitive && !host.eq_ignore_ascii_case(&original_host) {
+                debug!("Not sending the sensitive header '{}' to '{}', as it is not the original host.", header.name, host);
+            } else {
+                request = request.set(&header.name, &header.value);
+            }
+        }
+        let secure = url.scheme() == "https";
+        let cookie_header = jar
+            .iter()
+            .filter(|c| c.matches_host(&host) && (secure || !c.secure))
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        if !cookie_header.is_empty() {
+            request = request.set("Cookie", &cookie_header);
+        }
+
+        let response = call_with_dns_retry(request)?;
+        for header in response.all("Set-Cookie") {
+            if let Some(cookie) = Cookie::parse_set_cookie(header, &host) {
+                jar.retain(|c| !(c.name == cookie.name && c.domain.eq_ignore_ascii_case(&cookie.domain)));
+                jar.push(cookie);
+            }
+        }
+
+        match response.header("Location") {
+            Some(location) if (300..400).contains(&response.status()) => {
+                let was_https = url.scheme() == "https";
+                url = url.join(location).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                if was_https && url.scheme() != "https" {
+                    // sensitive headers and cookies would otherwise be sent in plain text
+                    let msg = format!("Refusing to follow redirect from https to '{}'.", util::sanitize_url(url.as_str()));
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, msg).into());
+                }
+                let target_host = url.host_str().unwrap_or_default();
+                let allowed = options.allowed_redirect_hosts.is_empty()
+                    || target_host.eq_ignore_ascii_case(&original_host)
+                    || options.allowed_redirect_hosts.iter().any(|h| h.eq_ignore_ascii_case(target_host));
+                if !allowed {
+                    let msg = format!(
+                        "Refusing to follow redirect to '{}', as '{}' is not an allowed redirect host.",
+                        util::sanitize_url(url.as_str()),
+                        target_host
+                    );
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, msg).into());
+                }
+                debug!("Following redirect to: {}", util::sanitize_url(url.as_str()));
+            }
+            _ => return Ok(response),
+        }
+    }
+    Err(io::Error::other(format!("Too many redirects (more than {}).", max_redirects)).into())
+}
+
+/// Sends the request, retrying with a short backoff if it fails because the host name could not be resolved. Other
+/// failures (eg. a refused connection, or an error status) are returned immediately.
+#[allow(clippy::result_large_err)]
+fn call_with_dns_retry(request: ureq::Request) -> Result<ureq::Response, ureq::Error> {
+    for delay in DNS_RETRY_DELAYS {
+        match request.clone().call() {
+            Err(e) if is_dns_error(&e) => {
+                warn!(
+                    "DNS resolution failed for '{}', retrying in {}ms... (error was: {})",
+                    util::sanitize_url(request.url()),
+                    delay.as_millis(),
+                    util::sanitize_error(&e)
+                );
+                std::thread::sleep(delay);
+            }
+            result => return result,
+        }
+    }
+    request.call()
+}
+
+/// Returns true if the connection was reset or aborted by the other end (or an intermediary) rather than closed cleanly.
+/// These failures are often transient on long transfers, so a resumable download can continue where it stopped.
+pub fn is_connection_reset(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|e| e.downcast_ref::<io::Error>())
+        .any(|e| matches!(e.kind(), io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted | io::ErrorKind::BrokenPipe))
+}
+
+/// Returns true if the request failed because the host name could not be resolved, rather than while connecting or reading.
+pub fn is_dns_error(err: &ureq::Error) -> bool {
+    matches!(err, ureq::Error::Transport(t) if t.kind() == ureq::ErrorKind::Dns)
+}
+
+/// Tracks the number of requests in progress to each host, so that parallel downloads are limited per host.
+struct HostLimiter {
+    active: Mutex<HashMap<String, usize>>,
+    released: Condvar,
+}
+
+static HOST_LIMITER: LazyLock<HostLimiter> = LazyLock::new(|| HostLimiter { active: Mutex::new(HashMap::new()), released: Condvar::new() });
+
+/// A connection slot for a host, which is released when dropped.
+struct HostPermit {
+    host: String,
+}
+
+impl Drop for HostPermit {
+    fn drop(&mut self) {
+        let mut active = HOST_LIMITER.active.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = active.get_mut(&self.host) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&self.host);
+            }
+        }
+        HOST_LIMITER.released.notify_all();
+    }
+}
+
+/// Waits until fewer than the allowed number of requests are in progress to the host of the url, and claims a slot.
+fn acquire_host_permit(url: &str, options: &DownloadOptions) -> Result<HostPermit> {
+    let parsed = url::Url::parse(url)?;
+    let host = format!("{}:{}", parsed.host_str().unwrap_or_default(), parsed.port_or_known_default().unwrap_or_default());
+    let limit = options.max_connections_per_host.unwrap_or(DEFAULT_MAX_CONNECTIONS_PER_HOST).max(1);
+
+    let mut active = HOST_LIMITER.active.lock().unwrap_or_else(|e| e.into_inner());
+    while active.get(&host).copied().unwrap_or(0) >= limit {
+        active = HOST_LIMITER.released.wait(active).unwrap_or_else(|e| e.into_inner());
+    }
+    *active.entry(host.clone()).or_insert(0) += 1;
+    Ok(HostPermit { host })
+}
+
+/// Downloads the url to the specified file path. If the server closes the connection before sending the number of bytes
+/// declared by its `Content-Length` header, `VelopackError::IncompleteDownload` is returned. Any bytes sent beyond the declared
+/// length are discarded, so the written file is never longer than advertised. The partially written file is removed on failure.
+/// The progress callback should not panic, but if it does the download is aborted and an error is returned instead.
+/// If `DownloadOptions::resume_partial_downloads` is set, an existing partial file is resumed and kept on failure (see
+/// `open_resumed_download`), and downloads which fail because the connection was reset are resumed automatically (see
+/// `DownloadOptions::connection_reset_retries`). Returns the lowercase hex SHA1 checksum of the written file, which is computed
+/// as each chunk arrives so that verifying a large package does not need to read it back from disk.
+pub fn download_url_to_file<A>(url: &str, file_path: &str, options: &DownloadOptions, mut progress: A) -> Result<String>
 where
     A: FnMut(i16),
 {
-    let agent = get_download_agent()?;
-    let response = agent.get(url).call()?;
+    let max_resets =
+        if options.resume_partial_downloads { options.connection_reset_retries.unwrap_or(DEFAULT_CONNECTION_RESET_RETRIES) } else { 0 };
+    let mut resets = 0;
+    let result = loop {
+        match download_url_to_file_inner(url, file_path, options, &mut progress) {
+            Err(e) if resets < max_resets && is_connection_reset(&e) && Path::new(file_path).exists() => {
+                resets += 1;
+                warn!(
+                    "Connection was reset while downloading '{}', resuming ({} of {})... (error was: {})",
+                    util::sanitize_url(url),
+                    resets,
+                    max_resets,
+                    util::sanitize_error(&e)
+                );
+            }
+            result => break result,
+        }
+    };
+    if result.is_err() && !options.resume_partial_downloads && std::path::Path::new(file_path).exists() {
+        warn!("Download of '{}' failed, removing partial file.", file_path);
+        let _ = util::remove_file(Path::new(file_path), options.secure_delete);
+    }
+    result
+}
+
+fn download_url_to_file_inner<A>(url: &str, file_path: &str, options: &DownloadOptions, mut progress: A) -> Result<String>
+where
+    A: FnMut(i16),
+{
+    let agent = get_download_agent(options)?;
+    let existing_size = if options.resume_partial_downloads { std::fs::metadata(file_path).map(|m| m.len()).unwrap_or(0) } else { 0 };
 
-    let total_size = response.header("Content-Length").and_then(|s| s.parse::<u64>().ok());
-    let mut file = util::retry_io(|| File::create(file_path))?;
+    if options.parallel_download_parts > 1 && existing_size == 0 {
+        match download_in_parallel_parts(&agent, url, file_path, options, &mut progress) {
+            Ok(Some(checksum)) => return Ok(checksum),
+            Ok(None) => {}
+            Err(e) if e.downcast_ref::<ProgressPanicked>().is_some() || is_quota_error(&e) => return Err(e),
+            Err(e) => warn!(
+                "Parallel download of '{}' failed, retrying as a single stream. (error was: {})",
+                util::sanitize_url(url),
+                util::sanitize_error(&e)
+            ),
+        }
+    }
+
+    let _permit = acquire_host_permit(url, options)?;
+    let (response, mut file, downloaded, hasher) = if existing_size > 0 {
+        match open_resumed_download(&agent, url, file_path, existing_size, options)? {
+            ResumedDownload::Partial(response, file, hasher) => (response, file, existing_size, hasher),
+            ResumedDownload::Complete(checksum) => {
+                report_progress(&mut progress, 100)?;
+                return Ok(checksum);
+            }
+            ResumedDownload::Restart(response) => (response, util::retry_io(|| File::create(file_path))?, 0, hash::Sha1::default()),
+        }
+    } else {
+        let response = send_request(&agent, "GET", url, options, &[])?;
+        (response, util::retry_io(|| File::create(file_path))?, 0, hash::Sha1::default())
+    };
+
+    let (_, checksum) = copy_response(url, response, &mut file, downloaded, hasher, options, &mut progress)?;
+    Ok(checksum)
+}
+
+/// Downloads the url into a directory, naming the file with the filename from the server's `Content-Disposition` header
+/// (eg. when a pre-signed url serves a file under a different name), or `default_name` if there is none. Returns the path
+/// of the downloaded file, and its lowercase hex SHA1 checksum. The partially written file is removed on failure.
+pub fn download_url_to_directory<A>(
+    url: &str,
+    directory: &Path,
+    default_name: &str,
+    options: &DownloadOptions,
+    mut progress: A,
+) -> Result<(PathBuf, String)>
+where
+    A: FnMut(i16),
+{
+    let agent = get_download_agent(options)?;
+    let _permit = acquire_host_permit(url, options)?;
+    let response = send_request(&agent, "GET", url, options, &[])?;
+
+    let file_name =
+        response.header("Content-Disposition").and_then(content_disposition_file_name).unwrap_or_else(|| default_name.to_owned());
+    let file_path = directory.join(&file_name);
+    info!("Saving download of '{}' as '{}'.", util::sanitize_url(url), file_path.to_string_lossy());
+
+    let mut file = util::retry_io(|| File::create(&file_path))?;
+    match copy_response(url, response, &mut file, 0, hash::Sha1::default(), options, &mut progress) {
+        Ok((_, checksum)) => Ok((file_path, checksum)),
+        Err(e) => {
+            warn!("Download of '{}' failed, removing partial file.", file_path.to_string_lossy());
+            drop(file);
+            let _ = util::remove_file(&file_path, options.secure_delete);
+            Err(e)
+        }
+    }
+}
+
+/// Reads the filename from a `Content-Disposition` header, preferring the extended `filename*` parameter. Any directory
+/// components are removed, so the name can not be used to write outside of the download directory.
+fn content_disposition_file_name(header: &str) -> Option<String> {
+    let mut plain = None;
+    let mut extended = None;
+    for param in header.split(';').skip(1) {
+        let Some((key, value)) = param.split_once('=') else { continue };
+        let value = value.trim().trim_matches('"');
+        match key.trim().to_ascii_lowercase().as_str() {
+            // eg. filename*=UTF-8''My%20App.nupkg
+            "filename*" => extended = value.split_once("''").map(|(_, v)| util::percent_decode(v)),
+            "filename" => plain = Some(value.to_owned()),
+            _ => {}
+        }
+    }
+    let name = extended.or(plain)?;
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default().trim().to_owned();
+    if name.is_empty() || name == "." || name == ".." {
+        return None;
+    }
+    Some(name)
+}
+
+/// Downloads the url into the provided writer (eg. the stdin of an installer process), without writing it to disk.
+/// Like `download_url_to_file`, `VelopackError::IncompleteDownload` is returned if the body ends early. Nothing can be
+/// taken back from the writer on failure. Returns the number of bytes written, and their lowercase hex SHA1 checksum.
+pub fn download_url_to_writer<W, A>(url: &str, writer: &mut W, options: &DownloadOptions, mut progress: A) -> Result<(u64, String)>
+where
+    W: Write,
+    A: FnMut(i16),
+{
+    let agent = get_download_agent(options)?;
+    let _permit = acquire_host_permit(url, options)?;
+    let response = send_request(&agent, "GET", url, options, &[])?;
+    copy_response(url, response, writer, 0, hash::Sha1::default(), options, &mut progress)
+}
+
+/// Copies the response body into the writer, following `downloaded` bytes which have already been written (and hashed)
+/// by an earlier attempt. Returns the total number of bytes, and the checksum of all of them.
+fn copy_response<W, A>(
+    url: &str,
+    response: ureq::Response,
+    writer: &mut W,
+    mut downloaded: u64,
+    mut hasher: hash::Sha1,
+    options: &DownloadOptions,
+    progress: &mut A,
+) -> Result<(u64, String)>
+where
+    W: Write,
+    A: FnMut(i16),
+{
+    let total_size = response.header("Content-Length").and_then(|s| s.parse::<u64>().ok()).map(|len| len + downloaded);
+    let mut header_checksums =
+        if options.ignore_checksum_headers || downloaded > 0 { None } else { HeaderChecksums::from_response(&response) };
+    if let (Some(quota), Some(total_size)) = (&options.quota, total_size) {
+        quota.check(total_size - downloaded)?;
+    }
 
     const CHUNK_SIZE: usize = 2 * 1024 * 1024; // 2MB
-    let mut downloaded: u64 = 0;
     let mut buffer = vec![0; CHUNK_SIZE];
     let mut reader = response.into_reader();
 
     let mut last_progress = 0;
 
-    while let Ok(size) = reader.read(&mut buffer) {
+    loop {
+        let size = match reader.read(&mut buffer) {
+            Ok(size) => size,
+            // the stream ended before Content-Length bytes were read, this is reported below.
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && total_size.is_some() => break,
+            Err(e) => return Err(e.into()),
+        };
         if size == 0 {
             break; // End of stream
         }
-        file.write_all(&buffer[..size])?;
+        if let Some(quota) = &options.quota {
+            quota.consume(size as u64)?;
+        }
+        writer.write_all(&buffer[..size])?;
+        hasher.update(&buffer[..size]);
+        if let Some(header_checksums) = &mut header_checksums {
+            header_checksums.update(&buffer[..size]);
+        }
         downloaded += size as u64;
 
-        if total_size.is_some() {
+        if let Some(total_size) = total_size {
             // floor to nearest 5% to reduce message spam
-            let new_progress = (downloaded as f64 / total_size.unwrap() as f64 * 20.0).floor() as i16 * 5;
+            let new_progress = (downloaded as f64 / total_size as f64 * 20.0).floor() as i16 * 5;
             if new_progress > last_progress {
                 last_progress = new_progress;
-                progress(last_progress);
+                report_progress(progress, last_progress)?;
+            }
+        } else if last_progress != INDETERMINATE_PROGRESS {
+            last_progress = INDETERMINATE_PROGRESS;
+            report_progress(progress, last_progress)?;
+        }
+    }
+
+    if let Some(total_size) = total_size {
+        if downloaded < total_size {
+            error!("Download of '{}' ended after {} of {} bytes.", util::sanitize_url(url), downloaded, total_size);
+            return Err(VelopackError::IncompleteDownload { expected: total_size, actual: downloaded }.into());
+        }
+    }
+    if let Some(header_checksums) = header_checksums {
+        header_checksums.verify(url)?;
+    }
+
+    Ok((downloaded, hash::to_hex(&hasher.finish())))
+}
+
+/// The checksums of a whole response body declared by object store headers, and the hashers used to verify them.
+struct HeaderChecksums {
+    sha256: Option<(Vec<u8>, hash::Sha256)>,
+    md5: Option<(Vec<u8>, hash::Md5)>,
+}
+
+impl HeaderChecksums {
+    /// Reads the checksum headers from a response, returning None if there are none which can be verified. Partial responses
+    /// and encoded bodies are skipped, as the headers describe the whole stored object rather than the bytes received.
+    fn from_response(response: &ureq::Response) -> Option<HeaderChecksums> {
+        if response.status() != 200 {
+            return None;
+        }
+        HeaderChecksums::from_headers(response, true)
+    }
+
+    /// Reads the checksum headers of the whole stored object from a partial response (eg. the probe sent before a parallel
+    /// download). `Content-MD5` is skipped, as in a partial response it can describe just the bytes in the range.
+    fn from_partial_response(response: &ureq::Response) -> Option<HeaderChecksums> {
+        if response.status() != 206 {
+            return None;
+        }
+        HeaderChecksums::from_headers(response, false)
+    }
+
+    fn from_headers(response: &ureq::Response, content_md5: bool) -> Option<HeaderChecksums> {
+        if response.header("Content-Encoding").is_some_and(|e| !e.eq_ignore_ascii_case("identity")) {
+            return None;
+        }
+        let decode = |name: &str, len: usize| {
+            let value = response.header(name)?;
+            match base64::engine::general_purpose::STANDARD.decode(value.trim()) {
+                Ok(bytes) if bytes.len() == len => Some(bytes),
+                // eg. the checksum of a multipart upload, which is a checksum of the checksums of each part
+                _ => {
+                    debug!("Ignoring checksum header {} with unsupported value '{}'.", name, value);
+                    None
+                }
             }
+        };
+        let sha256 = decode("x-amz-checksum-sha256", 32).map(|expected| (expected, hash::Sha256::default()));
+        let md5 = content_md5
+            .then(|| decode("Content-MD5", 16))
+            .flatten()
+            .or_else(|| decode("x-ms-blob-content-md5", 16))
+            .map(|expected| (expected, hash::Md5::default()));
+        if sha256.is_none() && md5.is_none() {
+            return None;
         }
+        Some(HeaderChecksums { sha256, md5 })
     }
 
+    fn update(&mut self, data: &[u8]) {
+        if let Some((_, hasher)) = &mut self.sha256 {
+            hasher.update(data);
+        }
+        if let Some((_, hasher)) = &mut self.md5 {
+            hasher.update(data);
+        }
+    }
+
+    /// Returns `VelopackError::ChecksumMismatch` if the body does not match one of the checksum headers.
+    fn verify(self, url: &str) -> Result<()> {
+        let actual_sha256 = self.sha256.map(|(expected, hasher)| (expected, hasher.finish().to_vec()));
+        let actual_md5 = self.md5.map(|(expected, hasher)| (expected, hasher.finish().to_vec()));
+        for (name, expected, actual) in
+            [("SHA-256", actual_sha256), ("MD5", actual_md5)].into_iter().filter_map(|(n, c)| c.map(|(e, a)| (n, e, a)))
+        {
+            if expected != actual {
+                let (expected, actual) = (hash::to_hex(&expected), hash::to_hex(&actual));
+                error!(
+                    "Download of '{}' has a {} checksum of {}, but the server declared {}.",
+                    util::sanitize_url(url),
+                    name,
+                    actual,
+                    expected
+                );
+                return Err(VelopackError::ChecksumMismatch { expected, actual }.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+enum ResumedDownload {
+    /// The server is sending the remaining bytes, which should be appended to the file (already hashed up to this point).
+    Partial(ureq::Response, File, hash::Sha1),
+    /// The file on disk is already as large as the remote resource, and has this checksum.
+    Complete(String),
+    /// The partial file can not be resumed, and the response contains the whole resource.
+    Restart(ureq::Response),
+}
+
+/// Requests the bytes after those already present in a partial file. If the server responds with `416 Range Not Satisfiable`
+/// and reports a total size equal to the partial file, the file is treated as complete (the caller is still responsible for
+/// verifying it against the feed). If the partial file is larger than the remote resource, the server ignores the range,
+/// or the total size is not reported, the download is restarted from zero.
+fn open_resumed_download(
+    agent: &ureq::Agent,
+    url: &str,
+    file_path: &str,
+    existing_size: u64,
+    options: &DownloadOptions,
+) -> Result<ResumedDownload> {
+    let range = format!("bytes={}-", existing_size);
+    match send_request(agent, "GET", url, options, &[("Range", &range)]) {
+        Ok(response) if response.status() == 206 => {
+            info!("Resuming download of '{}' from byte {}.", util::sanitize_url(url), existing_size);
+            let mut hasher = hash::Sha1::default();
+            let mut partial = File::open(file_path)?;
+            let mut buffer = vec![0; 64 * 1024];
+            loop {
+                let len = partial.read(&mut buffer)?;
+                if len == 0 {
+                    break;
+                }
+                hasher.update(&buffer[..len]);
+            }
+            let file = std::fs::OpenOptions::new().append(true).open(file_path)?;
+            Ok(ResumedDownload::Partial(response, file, hasher))
+        }
+        Ok(response) => {
+            info!("Server ignored the range request for '{}', restarting the download.", util::sanitize_url(url));
+            Ok(ResumedDownload::Restart(response))
+        }
+        Err(ureq::Error::Status(416, response)) => {
+            let total = response.header("Content-Range").and_then(|s| s.rsplit_once('/')).and_then(|(_, total)| total.parse::<u64>().ok());
+            if total == Some(existing_size) {
+                info!("Partial file '{}' is already the full size of the remote resource ({} bytes).", file_path, existing_size);
+                return Ok(ResumedDownload::Complete(hash::sha1_file(file_path)?));
+            }
+            warn!(
+                "Partial file '{}' ({} bytes) does not fit the remote resource ({:?} bytes), restarting the download.",
+                file_path, existing_size, total
+            );
+            Ok(ResumedDownload::Restart(send_request(agent, "GET", url, options, &[])?))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Downloads the url as several byte ranges in parallel, each written to its own region of the pre-allocated file.
+/// Returns None without downloading anything if the file is smaller than the threshold, or the server does not support
+/// range requests. Each part holds its own host permit, and progress is aggregated across all of them.
+fn download_in_parallel_parts<A>(
+    agent: &ureq::Agent,
+    url: &str,
+    file_path: &str,
+    options: &DownloadOptions,
+    progress: &mut A,
+) -> Result<Option<String>>
+where
+    A: FnMut(i16),
+{
+    let (total_size, header_checksums) = {
+        let _permit = acquire_host_permit(url, options)?;
+        let response = send_request(agent, "GET", url, options, &[("Range", "bytes=0-0")])?;
+        let total = response.header("Content-Range").and_then(|s| s.rsplit_once('/')).and_then(|(_, total)| total.parse::<u64>().ok());
+        let checksums = if options.ignore_checksum_headers { None } else { HeaderChecksums::from_partial_response(&response) };
+        match total {
+            Some(total) if response.status() == 206 => (total, checksums),
+            _ => {
+                info!("Server does not support range requests for '{}', downloading as a single stream.", util::sanitize_url(url));
+                return Ok(None);
+            }
+        }
+    };
+    if total_size < options.parallel_download_threshold.max(1) {
+        return Ok(None);
+    }
+    if let Some(quota) = &options.quota {
+        quota.check(total_size)?;
+    }
+
+    let parts = (options.parallel_download_parts as u64).min(total_size);
+    let part_size = total_size.div_ceil(parts);
+    info!("Downloading '{}' ({} bytes) in {} parallel parts.", util::sanitize_url(url), total_size, parts);
+    util::retry_io(|| File::create(file_path))?.set_len(total_size)?;
+
+    let downloaded = AtomicU64::new(0);
+    let aborted = AtomicBool::new(false);
+    let result = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..parts)
+            .map(|i| {
+                let (start, end) = (i * part_size, ((i + 1) * part_size).min(total_size) - 1);
+                let (downloaded, aborted) = (&downloaded, &aborted);
+                scope.spawn(move || {
+                    let result = download_part(agent, url, file_path, options, start..=end, downloaded, aborted);
+                    if result.is_err() {
+                        aborted.store(true, AtomicOrdering::SeqCst);
+                    }
+                    result
+                })
+            })
+            .collect();
+
+        // the progress callback is not Send, so it is invoked from this thread while the parts download
+        let mut last_progress = 0;
+        while handles.iter().any(|h| !h.is_finished()) {
+            std::thread::sleep(Duration::from_millis(50));
+            let new_progress = ((downloaded.load(AtomicOrdering::SeqCst) * 20 / total_size) * 5) as i16;
+            if new_progress > last_progress && new_progress < 100 {
+                last_progress = new_progress;
+                if let Err(e) = report_progress(progress, last_progress) {
+                    aborted.store(true, AtomicOrdering::SeqCst);
+                    return Err(e);
+                }
+            }
+        }
+        handles.into_iter().try_for_each(|h| h.join().unwrap_or_else(|_| Err(anyhow!("A download part panicked."))))
+    });
+    let checksum = match result.and_then(|_| hash_assembled_parts(url, file_path, header_checksums)) {
+        Ok(checksum) => checksum,
+        Err(e) => {
+            // the parts are discarded (and downloaded again as a single stream), so they should not count against the quota twice
+            if let Some(quota) = &options.quota {
+                quota.refund(downloaded.load(AtomicOrdering::SeqCst));
+            }
+            return Err(e);
+        }
+    };
+
+    report_progress(progress, 100)?;
+    Ok(Some(checksum))
+}
+
+/// Computes the SHA1 checksum of a file assembled from parallel parts, verifying it against the checksum headers of the
+/// whole object (if the server sent any), since none of the parts could be checked on their own.
+fn hash_assembled_parts(url: &str, file_path: &str, mut header_checksums: Option<HeaderChecksums>) -> Result<String> {
+    let mut file = File::open(file_path)?;
+    let mut hasher = hash::Sha1::default();
+    let mut buffer = vec![0; 256 * 1024];
+    loop {
+        let size = file.read(&mut buffer)?;
+        if size == 0 {
+            break;
+        }
+        hasher.update(&buffer[..size]);
+        if let Some(header_checksums) = &mut header_checksums {
+            header_checksums.update(&buffer[..size]);
+        }
+    }
+    if let Some(header_checksums) = header_checksums {
+        header_checksums.verify(url)?;
+    }
+    Ok(hash::to_hex(&hasher.finish()))
+}
+
+fn download_part(
+    agent: &ureq::Agent,
+    url: &str,
+    file_path: &str,
+    options: &DownloadOptions,
+    range: std::ops::RangeInclusive<u64>,
+    downloaded: &AtomicU64,
+    aborted: &AtomicBool,
+) -> Result<()> {
+    let _permit = acquire_host_permit(url, options)?;
+    let header = format!("bytes={}-{}", range.start(), range.end());
+    let response = send_request(agent, "GET", url, options, &[("Range", &header)])?;
+    if response.status() != 206 {
+        bail!("Server responded to the range request '{}' with status {}.", header, response.status());
+    }
+
+    let expected = range.end() - range.start() + 1;
+    let mut file = std::fs::OpenOptions::new().write(true).open(file_path)?;
+    file.seek(io::SeekFrom::Start(*range.start()))?;
+    let mut reader = response.into_reader().take(expected);
+    let mut buffer = vec![0; 256 * 1024];
+    let mut written = 0;
+    loop {
+        if aborted.load(AtomicOrdering::SeqCst) {
+            bail!("The download of range '{}' was aborted.", header);
+        }
+        let size = reader.read(&mut buffer)?;
+        if size == 0 {
+            break;
+        }
+        if let Some(quota) = &options.quota {
+            quota.consume(size as u64)?;
+        }
+        // counted before writing, so the bytes are refunded to the quota even if the write fails
+        downloaded.fetch_add(size as u64, AtomicOrdering::SeqCst);
+        file.write_all(&buffer[..size])?;
+        written += size as u64;
+    }
+    if written < expected {
+        return Err(VelopackError::IncompleteDownload { expected, actual: written }.into());
+    }
     Ok(())
 }
 
-pub fn download_url_as_string(url: &str) -> Result<String> {
-    let agent = get_download_agent()?;
-    let r = agent.get(url).call()?.into_string()?;
-    Ok(r)
+/// Returned when the progress callback panics, so the download is aborted rather than retried.
+#[derive(Debug)]
+struct ProgressPanicked(i16);
+
+impl std::fmt::Display for ProgressPanicked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The progress callback panicked at {}%.", self.0)
+    }
+}
+
+impl std::error::Error for ProgressPanicked {}
+
+/// Invokes the progress callback, converting a panic inside it into an error so the download can be cleaned up.
+fn report_progress<A: FnMut(i16)>(progress: &mut A, value: i16) -> Result<()> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| progress(value))).map_err(|_| ProgressPanicked(value).into())
+}
+
+#[allow(dead_code)]
+pub fn download_url_as_string(url: &str, options: &DownloadOptions) -> Result<String> {
+    Ok(String::from_utf8(download_url_as_bytes(url, options)?)?)
+}
+
+pub fn download_url_as_bytes(url: &str, options: &DownloadOptions) -> Result<Vec<u8>> {
+    let agent = get_download_agent(options)?;
+    let _permit = acquire_host_permit(url, options)?;
+    read_feed_body(url, send_request(&agent, "GET", url, options, &[])?, options)
+}
+
+/// Reads a response body into memory, returning `VelopackError::FeedTooLarge` if it is larger than the maximum feed size
+/// (see `DownloadOptions::max_feed_size`). This is checked against `Content-Length` before anything is read, and again
+/// while reading in case the server does not declare the length.
+fn read_feed_body(url: &str, response: ureq::Response, options: &DownloadOptions) -> Result<Vec<u8>> {
+    let limit = options.max_feed_size.unwrap_or(DEFAULT_MAX_FEED_SIZE);
+    let too_large = || {
+        error!("Response from '{}' is larger than the maximum feed size of {} bytes.", util::sanitize_url(url), limit);
+        VelopackError::FeedTooLarge { limit }
+    };
+    if response.header("Content-Length").and_then(|s| s.parse::<u64>().ok()).is_some_and(|len| len > limit) {
+        return Err(too_large().into());
+    }
+    let mut bytes = Vec::new();
+    response.into_reader().take(limit.saturating_add(1)).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > limit {
+        return Err(too_large().into());
+    }
+    Ok(bytes)
+}
+
+/// A previously downloaded response body, along with the validators the server sent for it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CachedResponse {
+    pub body: Vec<u8>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// Downloads the url, sending a conditional request if a cached copy is provided so that an unchanged resource is not
+/// transferred again. An `ETag` is always preferred (sent as `If-None-Match`), as it does not depend on any clock. Otherwise
+/// the `Last-Modified` value the server sent is echoed back verbatim as `If-Modified-Since`, so the local clock is never
+/// used, but date based caching is still best-effort: a server whose own clock is skewed may send the full response again
+/// (which is simply used), or in rare cases report a changed resource as unmodified until it changes again.
+pub fn download_url_as_bytes_cached(url: &str, options: &DownloadOptions, cached: Option<&CachedResponse>) -> Result<CachedResponse> {
+    let agent = get_download_agent(options)?;
+    let _permit = acquire_host_permit(url, options)?;
+    let condition = cached.and_then(|c| match (&c.etag, &c.last_modified) {
+        (Some(etag), _) => Some(("If-None-Match", etag.as_str())),
+        (None, Some(date)) => Some(("If-Modified-Since", date.as_str())),
+        _ => None,
+    });
+    let response = send_request(&agent, "GET", url, options, condition.as_slice())?;
+    if let (304, Some(cached)) = (response.status(), cached) {
+        debug!("Server reports '{}' is unmodified, using the cached copy.", util::sanitize_url(url));
+        return Ok(cached.clone());
+    }
+
+    let etag = response.header("ETag").map(|s| s.to_owned());
+    let last_modified = response.header("Last-Modified").map(|s| s.to_owned());
+    let body = read_feed_body(url, response, options)?;
+    Ok(CachedResponse { body, etag, last_modified })
 }
 
-fn get_download_agent() -> Result<ureq::Agent> {
-    let tls_builder = native_tls::TlsConnector::builder();
-    let tls_connector = tls_builder.build()?;
-    Ok(ureq::AgentBuilder::new().tls_connector(tls_connector.into()).build())
+/// The part of a growing (append-only) file which follows a known offset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AppendedBytes {
+    /// The bytes appended after the offset, which may be empty if nothing has been appended.
+    Bytes(Vec<u8>),
+    /// The file is now shorter than the offset, so it has been rewritten rather than appended to.
+    Truncated,
+}
+
+/// The end of a remote file, see `download_url_tail`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TailBytes {
+    /// The last bytes of the file, which is longer than the requested length.
+    Partial(Vec<u8>),
+    /// The whole file, because it is no longer than the requested length, or the server does not support range requests.
+    Complete(Vec<u8>),
+}
+
+/// Downloads only the last `len` bytes of a file with a suffix range request.
+pub fn download_url_tail(url: &str, options: &DownloadOptions, len: u64) -> Result<TailBytes> {
+    let agent = get_download_agent(options)?;
+    let _permit = acquire_host_permit(url, options)?;
+    let range = format!("bytes=-{}", len);
+    let response = match send_request(&agent, "GET", url, options, &[("Range", &range)]) {
+        Ok(response) => response,
+        Err(ureq::Error::Status(416, _)) => return Ok(TailBytes::Complete(Vec::new())),
+        Err(e) => return Err(e.into()),
+    };
+
+    let content_range = response.header("Content-Range").map(|r| r.to_owned());
+    let partial = response.status() == 206;
+    if partial && !content_range.as_deref().is_some_and(|r| r.starts_with("bytes ")) {
+        bail!("Server responded to the range request '{}' without a valid Content-Range.", range);
+    }
+    let bytes = read_feed_body(url, response, options)?;
+    if partial && !content_range.is_some_and(|r| r.starts_with("bytes 0-")) {
+        return Ok(TailBytes::Partial(bytes));
+    }
+    Ok(TailBytes::Complete(bytes))
+}
+
+/// Downloads only the bytes after `offset` with a range request. Servers which ignore the range are handled by skipping
+/// the bytes which have already been read.
+pub fn download_url_after_offset(url: &str, options: &DownloadOptions, offset: u64) -> Result<AppendedBytes> {
+    let agent = get_download_agent(options)?;
+    let _permit = acquire_host_permit(url, options)?;
+    let range = format!("bytes={}-", offset);
+    let headers: &[(&str, &str)] = if offset > 0 { &[("Range", &range)] } else { &[] };
+    let response = match send_request(&agent, "GET", url, options, headers) {
+        Ok(response) => response,
+        Err(ureq::Error::Status(416, response)) => {
+            let total = response.header("Content-Range").and_then(|s| s.rsplit_once('/')).and_then(|(_, total)| total.parse::<u64>().ok());
+            return Ok(match total {
+                Some(total) if total < offset => AppendedBytes::Truncated,
+                _ => AppendedBytes::Bytes(Vec::new()),
+            });
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let partial = response.status() == 206;
+    if partial && !response.header("Content-Range").is_some_and(|r| r.starts_with(&format!("bytes {}-", offset))) {
+        bail!("Server responded to the range request '{}' with a different range.", range);
+    }
+    let mut bytes = read_feed_body(url, response, options)?;
+    if partial {
+        return Ok(AppendedBytes::Bytes(bytes));
+    }
+    match usize::try_from(offset).ok().filter(|o| *o <= bytes.len()) {
+        Some(offset) => Ok(AppendedBytes::Bytes(bytes.split_off(offset))),
+        None => Ok(AppendedBytes::Truncated),
+    }
+}
+
+/// Determines the size of the remote resource without downloading it. This sends a HEAD request, and if that is rejected
+/// (405 or 403, as some proxies do) falls back to a GET for only the first byte, reading the size from `Content-Range`.
+/// Returns None if the server does not report a size.
+pub fn probe_content_length(url: &str, options: &DownloadOptions) -> Result<Option<u64>> {
+    let agent = get_download_agent(options)?;
+    let _permit = acquire_host_permit(url, options)?;
+    match send_request(&agent, "HEAD", url, options, &[]) {
+        Ok(response) => return Ok(response.header("Content-Length").and_then(|s| s.parse::<u64>().ok())),
+        Err(ureq::Error::Status(code, _)) if code == 405 || code == 403 => {
+            info!("HEAD request was rejected with status {}, retrying with a ranged GET.", code);
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    let response = send_request(&agent, "GET", url, options, &[("Range", "bytes=0-0")])?;
+    if response.status() == 206 {
+        let total = response.header("Content-Range").and_then(|s| s.rsplit_once('/')).and_then(|(_, total)| total.parse::<u64>().ok());
+        return Ok(total);
+    }
+    Ok(response.header("Content-Length").and_then(|s| s.parse::<u64>().ok()))
+}
+
+fn get_download_agent(options: &DownloadOptions) -> Result<ureq::Agent> {
+    let mut tls_builder = native_tls::TlsConnector::builder();
+    for cert in &options.root_certificates {
+        tls_builder.add_root_certificate(cert.clone());
+    }
+    if options.danger_accept_invalid_certs {
+        warn!("Certificate validation is disabled (danger_accept_invalid_certs), this must not be used outside of development.");
+        tls_builder.danger_accept_invalid_certs(true);
+    }
+    let mut agent = if options.pinned_public_keys.is_empty() {
+        ureq::AgentBuilder::new().tls_connector(tls_builder.build()?.into())
+    } else {
+        ureq::AgentBuilder::new().tls_connector(Arc::new(PinnedTlsConnector::new(options)?))
+    };
+    let ip_stack = options.ip_stack;
+    match options.resolver.clone() {
+        Some(resolver) => agent = agent.resolver(move |netloc: &str| ip_stack.select_addresses(netloc, resolver(netloc)?)),
+        None if ip_stack != IpStack::System => {
+            agent = agent.resolver(move |netloc: &str| {
+                ip_stack.select_addresses(netloc, std::net::ToSocketAddrs::to_socket_addrs(netloc)?.collect())
+            })
+        }
+        None => {}
+    }
+    if options.follows_redirects_manually() {
+        // redirects are followed by send_request, so cookies can be attached and hosts checked at each step
+        agent = agent.redirects(0);
+    } else {
+        // ureq counts the original request towards its limit, so allow one more to follow the configured number of redirects
+        agent = agent.redirects(options.max_redirects.unwrap_or(DEFAULT_MAX_REDIRECTS) + 1);
+    }
+    Ok(agent.build())
+}
+
+/// Connects with rustls rather than the platform TLS library, because the platform connector only exposes the server
+/// (leaf) certificate, and the pins need to be checked against every certificate in the chain the server presents.
+struct PinnedTlsConnector {
+    config: Arc<rustls::ClientConfig>,
+}
+
+impl PinnedTlsConnector {
+    fn new(options: &DownloadOptions) -> Result<PinnedTlsConnector> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let inner = if options.danger_accept_invalid_certs {
+            None
+        } else {
+            let mut roots = rustls::RootCertStore::empty();
+            let native = rustls_native_certs::load_native_certs();
+            for e in native.errors {
+                warn!("Unable to load a certificate from the platform trust store: {}", e);
+            }
+            roots.add_parsable_certificates(native.certs);
+            for cert in &options.root_certificates {
+                roots.add(rustls::pki_types::CertificateDer::from(cert.to_der()?))?;
+            }
+            Some(rustls::client::WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone()).build()?)
+        };
+        let verifier = PinnedCertificateVerifier { inner, pins: options.pinned_public_keys.clone(), provider: provider.clone() };
+        let config = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth();
+        Ok(PinnedTlsConnector { config: Arc::new(config) })
+    }
+}
+
+impl ureq::TlsConnector for PinnedTlsConnector {
+    fn connect(&self, dns_name: &str, mut io: Box<dyn ureq::ReadWrite>) -> Result<Box<dyn ureq::ReadWrite>, ureq::Error> {
+        let server_name = rustls::pki_types::ServerName::try_from(dns_name.to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid server name '{}': {}", dns_name, e)))?;
+        let mut conn = rustls::ClientConnection::new(self.config.clone(), server_name).map_err(io::Error::other)?;
+        // finish the handshake here, so a pin mismatch fails the connection rather than the first read of the response
+        while conn.is_handshaking() {
+            conn.complete_io(&mut io).map_err(|e| io::Error::new(e.kind(), format!("TLS handshake failed: {}", e)))?;
+        }
+        Ok(Box::new(PinnedTlsStream(rustls::StreamOwned::new(conn, io))))
+    }
+}
+
+/// Performs the usual certificate validation (unless invalid certificates are accepted), and then rejects the server
+/// unless the public key of its certificate, or of one of the intermediate certificates it sent, is pinned.
+#[derive(Debug)]
+struct PinnedCertificateVerifier {
+    inner: Option<Arc<rustls::client::WebPkiServerVerifier>>,
+    pins: Vec<String>,
+    provider: Arc<rustls::crypto::CryptoProvider>,
+}
+
+impl rustls::client::danger::ServerCertVerifier for PinnedCertificateVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::pki_types::CertificateDer<'_>,
+        intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        server_name: &rustls::pki_types::ServerName<'_>,
+        ocsp_response: &[u8],
+        now: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        if let Some(inner) = &self.inner {
+            inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+        }
+        let presented: Vec<String> = std::iter::once(end_entity).chain(intermediates).filter_map(|cert| public_key_pin(cert)).collect();
+        if presented.is_empty() {
+            return Err(rustls::Error::General("Unable to read server certificate.".to_string()));
+        }
+        if !presented.iter().any(|pin| self.pins.iter().any(|p| p.trim_start_matches("sha256/") == pin)) {
+            let presented = presented.iter().map(|pin| format!("sha256/{}", pin)).collect::<Vec<_>>().join(", ");
+            let msg = format!("The certificate chain of '{}' ({}) does not match any pinned public key.", server_name.to_str(), presented);
+            return Err(rustls::Error::General(msg));
+        }
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[derive(Debug)]
+struct PinnedTlsStream(rustls::StreamOwned<rustls::ClientConnection, Box<dyn ureq::ReadWrite>>);
+
+impl Read for PinnedTlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // a connection closed without close_notify may have been truncated, so this is an error rather than the end of the
+        // response. Responses with a declared length are not read past their end, so are not affected.
+        self.0.read(buf).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => {
+                io::Error::new(e.kind(), "The connection was closed without a TLS close_notify, the response may be incomplete.")
+            }
+            _ => e,
+        })
+    }
+}
+
+impl Write for PinnedTlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl ureq::ReadWrite for PinnedTlsStream {
+    fn socket(&self) -> Option<&std::net::TcpStream> {
+        self.0.get_ref().socket()
+    }
+}
+
+/// Parses every certificate in a PEM encoded bundle (eg. a CA bundle file containing several certificates).
+pub fn parse_pem_certificates(pem: &[u8]) -> Result<Vec<native_tls::Certificate>> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const END: &str = "-----END CERTIFICATE-----";
+    let text = std::str::from_utf8(pem)?;
+    let mut certs = Vec::new();
+    let mut remaining = text;
+    while let Some(start) = remaining.find(BEGIN) {
+        let end = remaining[start..].find(END).ok_or_else(|| anyhow!("Unterminated certificate in PEM data."))? + start + END.len();
+        certs.push(native_tls::Certificate::from_pem(&remaining.as_bytes()[start..end])?);
+        remaining = &remaining[end..];
+    }
+    if certs.is_empty() {
+        bail!("No certificates found in PEM data.");
+    }
+    Ok(certs)
+}
+
+/// Computes the base64 encoded SHA-256 hash of the SubjectPublicKeyInfo in a DER encoded X.509 certificate.
+fn public_key_pin(cert_der: &[u8]) -> Option<String> {
+    // Certificate ::= SEQUENCE { tbsCertificate SEQUENCE { [0] version OPTIONAL, serialNumber, signature,
+    //                            issuer, validity, subject, subjectPublicKeyInfo, ... }, ... }
+    let (_, cert, _) = read_der(cert_der)?;
+    let (_, mut tbs, _) = read_der(cert)?;
+    let (tag, _, rest) = read_der(tbs)?;
+    if tag == 0xa0 {
+        tbs = rest;
+    }
+    for _ in 0..5 {
+        tbs = read_der(tbs)?.2;
+    }
+    let (_, _, rest) = read_der(tbs)?;
+    let spki = &tbs[..tbs.len() - rest.len()];
+    Some(base64::engine::general_purpose::STANDARD.encode(hash::sha256(spki)))
+}
+
+/// Reads a single DER element, returning its tag, its contents, and the remaining bytes after the element.
+fn read_der(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *data.first()?;
+    let first_len = *data.get(1)? as usize;
+    let (len, header_len) = if first_len < 0x80 {
+        (first_len, 2)
+    } else {
+        let len_bytes = first_len & 0x7f;
+        if len_bytes == 0 || len_bytes > 4 {
+            return None;
+        }
+        let mut len = 0usize;
+        for i in 0..len_bytes {
+            len = (len << 8) | *data.get(2 + i)? as usize;
+        }
+        (len, 2 + len_bytes)
+    };
+    let end = header_len.checked_add(len)?;
+    if end > data.len() {
+        return None;
+    }
+    Some((tag, &data[header_len..end], &data[end..]))
 }
 
 #[test]
 fn test_download_uses_tls_and_encoding_correctly() {
-    assert_eq!(download_url_as_string("https://dotnetcli.blob.core.windows.net/dotnet/WindowsDesktop/5.0/latest.version").unwrap(), "5.0.17");
+    assert_eq!(
+        download_url_as_string(
+            "https://dotnetcli.blob.core.windows.net/dotnet/WindowsDesktop/5.0/latest.version",
+            &DownloadOptions::default()
+        )
+        .unwrap(),
+        "5.0.17"
+    );
 }
 
 #[test]
@@ -64,7 +1382,7 @@ fn test_download_file_reports_progress() {
     let mut prog_count = 0;
     let mut last_prog = 0;
 
-    download_url_to_file(test_file, "test_download_file_reports_progress.txt", |p| {
+    download_url_to_file(test_file, "test_download_file_reports_progress.txt", &DownloadOptions::default(), |p| {
         assert!(p >= last_prog);
         prog_count += 1;
         last_prog = p;
@@ -82,3 +1400,712 @@ fn test_download_file_reports_progress() {
     assert_eq!(len, 10 * 1024 * 1024);
     std::fs::remove_file(p).unwrap();
 }
+
+#[cfg(test)]
+const FIXTURE_LOCALHOST_PIN: &str = "wBKht7M/8iAUM1qlOLqx92rIRcVNWNSAcv/bAOuGp+c=";
+
+#[cfg(test)]
+const FIXTURE_CA_PIN: &str = "rdlHPPl3PW/n6UGUg6gS/XDyy9kLrkRVYw1bB5Djj2U=";
+
+#[cfg(test)]
+fn get_pinned_test_agent(pins: &[&str]) -> ureq::Agent {
+    let options = DownloadOptions {
+        pinned_public_keys: pins.iter().map(|p| p.to_string()).collect(),
+        root_certificates: vec![crate::testing::fixture_ca_certificate()],
+        ..Default::default()
+    };
+    get_download_agent(&options).unwrap()
+}
+
+#[test]
+fn test_public_key_pin_is_computed_from_certificate() {
+    let cert = native_tls::Certificate::from_pem(include_bytes!("../fixtures/localhost.pem")).unwrap();
+    assert_eq!(public_key_pin(&cert.to_der().unwrap()).unwrap(), FIXTURE_LOCALHOST_PIN);
+}
+
+#[test]
+fn test_pinned_public_key_allows_matching_connection() {
+    let server = crate::testing::serve_tls(|_| crate::testing::MockResponse::ok("pinned"));
+    let agent = get_pinned_test_agent(&["sha256/AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=", FIXTURE_LOCALHOST_PIN]);
+    let body = agent.get(&server.url).call().unwrap().into_string().unwrap();
+    assert_eq!(body, "pinned");
+}
+
+#[test]
+fn test_pinned_public_key_rejects_mismatched_connection() {
+    let server = crate::testing::serve_tls(|_| crate::testing::MockResponse::ok("pinned"));
+    let agent = get_pinned_test_agent(&["AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="]);
+    let err = agent.get(&server.url).call().unwrap_err();
+    assert!(format!("{:?}", err).contains("does not match any pinned public key"));
+}
+
+#[test]
+fn test_pinned_public_key_matches_ca_in_presented_chain() {
+    let server = crate::testing::serve_tls(|_| crate::testing::MockResponse::ok("pinned"));
+    let agent = get_pinned_test_agent(&[FIXTURE_CA_PIN]);
+    let body = agent.get(&server.url).call().unwrap().into_string().unwrap();
+    assert_eq!(body, "pinned");
+}
+
+#[test]
+fn test_pinned_public_key_is_checked_when_invalid_certificates_are_accepted() {
+    let server = crate::testing::serve_tls(|_| crate::testing::MockResponse::ok("pinned"));
+    let options =
+        |pin: &str| DownloadOptions { pinned_public_keys: vec![pin.to_string()], danger_accept_invalid_certs: true, ..Default::default() };
+    let body = get_download_agent(&options(FIXTURE_LOCALHOST_PIN)).unwrap().get(&server.url).call().unwrap().into_string().unwrap();
+    assert_eq!(body, "pinned");
+    assert!(get_download_agent(&options("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=")).unwrap().get(&server.url).call().is_err());
+}
+
+#[test]
+fn test_pinned_connection_closed_without_close_notify_is_incomplete() {
+    let server = crate::testing::serve_tls(|req| match req.path.as_str() {
+        // no Content-Length, so the end of the body is only signalled by closing the connection
+        "/close-delimited" => crate::testing::MockResponse::ok("pinned").with_header("Transfer-Encoding", "identity"),
+        _ => crate::testing::MockResponse::ok("pinned"),
+    });
+    let options = DownloadOptions {
+        pinned_public_keys: vec![FIXTURE_CA_PIN.to_string()],
+        root_certificates: vec![crate::testing::fixture_ca_certificate()],
+        ..Default::default()
+    };
+    let mut body = Vec::new();
+    download_url_to_writer(&server.url, &mut body, &options, |_| {}).unwrap();
+    assert_eq!(body, b"pinned");
+
+    let err = download_url_to_writer(&format!("{}/close-delimited", server.url), &mut Vec::new(), &options, |_| {}).unwrap_err();
+    assert!(format!("{:?}", err).contains("close_notify"));
+}
+
+#[test]
+fn test_invalid_certificates_are_only_accepted_when_enabled() {
+    let server = crate::testing::serve_tls(|_| crate::testing::MockResponse::ok("untrusted"));
+    let strict = get_download_agent(&DownloadOptions::default()).unwrap();
+    assert!(strict.get(&server.url).call().is_err());
+
+    let options = DownloadOptions { danger_accept_invalid_certs: true, ..Default::default() };
+    let body = get_download_agent(&options).unwrap().get(&server.url).call().unwrap().into_string().unwrap();
+    assert_eq!(body, "untrusted");
+}
+
+#[test]
+fn test_download_is_verified_against_checksum_headers() {
+    use crate::testing::MockResponse;
+    let server = crate::testing::serve(|req| {
+        let response = MockResponse::ok("hello world");
+        match req.path.as_str() {
+            "/s3" => response.with_header("x-amz-checksum-sha256", "uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek="),
+            "/s3-corrupt" => response.with_header("x-amz-checksum-sha256", "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="),
+            "/s3-multipart" => response.with_header("x-amz-checksum-sha256", "dGVzdA==-3"),
+            "/azure" => response.with_header("Content-MD5", "XrY7u+Ae7tCTyyK7j1rNww=="),
+            "/azure-corrupt" => response.with_header("Content-MD5", "AAAAAAAAAAAAAAAAAAAAAA=="),
+            "/azure-blob-corrupt" => response.with_header("x-ms-blob-content-md5", "AAAAAAAAAAAAAAAAAAAAAA=="),
+            _ => response,
+        }
+    });
+    let download = |path: &str, options: &DownloadOptions| {
+        let mut output = Vec::new();
+        download_url_to_writer(&format!("{}{}", server.url, path), &mut output, options, |_| {}).map(|_| output)
+    };
+    let options = DownloadOptions::default();
+
+    for path in ["/s3", "/s3-multipart", "/azure", "/none"] {
+        assert_eq!(download(path, &options).unwrap(), b"hello world", "{}", path);
+    }
+    for path in ["/s3-corrupt", "/azure-corrupt", "/azure-blob-corrupt"] {
+        let err = download(path, &options).unwrap_err();
+        assert!(matches!(err.downcast_ref::<VelopackError>(), Some(VelopackError::ChecksumMismatch { .. })), "{}", path);
+    }
+    let err = download("/azure-corrupt", &options).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<VelopackError>(),
+        Some(&VelopackError::ChecksumMismatch { expected: "0".repeat(32), actual: "5eb63bbbe01eeed093cb22bb8f5acdc3".to_owned() })
+    );
+
+    let ignored = DownloadOptions { ignore_checksum_headers: true, ..Default::default() };
+    assert_eq!(download("/s3-corrupt", &ignored).unwrap(), b"hello world");
+}
+
+#[test]
+fn test_probe_content_length_uses_head() {
+    let server = crate::testing::serve(|_| crate::testing::MockResponse::ok("hello world"));
+    assert_eq!(probe_content_length(&server.url, &DownloadOptions::default()).unwrap(), Some(11));
+    assert_eq!(server.requests()[0].method, "HEAD");
+}
+
+#[test]
+fn test_probe_content_length_falls_back_to_ranged_get() {
+    use crate::testing::MockResponse;
+    let server = crate::testing::serve(|req| match (req.method.as_str(), req.header("Range")) {
+        ("HEAD", _) => MockResponse::status(405),
+        ("GET", Some("bytes=0-0")) => {
+            MockResponse { status: 206, headers: vec![("Content-Range".into(), "bytes 0-0/12345".into())], body: b"h".to_vec() }
+        }
+        _ => MockResponse::status(400),
+    });
+    assert_eq!(probe_content_length(&server.url, &DownloadOptions::default()).unwrap(), Some(12345));
+    let methods: Vec<String> = server.requests().iter().map(|r| r.method.clone()).collect();
+    assert_eq!(methods, vec!["HEAD", "GET"]);
+}
+
+#[test]
+fn test_download_file_rejects_truncated_body() {
+    let server = crate::testing::serve(|_| crate::testing::MockResponse::ok(vec![7u8; 10]).with_header("Content-Length", "20"));
+    let dir = crate::testing::temp_dir("download-truncated");
+    let file_path = dir.join("package.nupkg");
+    let file_path = file_path.to_str().unwrap();
+
+    let err = download_url_to_file(&format!("{}/package.nupkg", server.url), file_path, &DownloadOptions::default(), |_| {}).unwrap_err();
+    assert_eq!(err.downcast_ref::<VelopackError>(), Some(&VelopackError::IncompleteDownload { expected: 20, actual: 10 }));
+    assert!(!std::path::Path::new(file_path).exists());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_download_file_discards_bytes_past_content_length() {
+    let server = crate::testing::serve(|_| crate::testing::MockResponse::ok(vec![7u8; 10]).with_header("Content-Length", "4"));
+    let dir = crate::testing::temp_dir("download-overlong");
+    let file_path = dir.join("package.nupkg");
+    let file_path = file_path.to_str().unwrap();
+
+    download_url_to_file(&format!("{}/package.nupkg", server.url), file_path, &DownloadOptions::default(), |_| {}).unwrap();
+    assert_eq!(std::fs::read(file_path).unwrap(), vec![7u8; 4]);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_download_file_returns_checksum_of_written_file() {
+    let body: Vec<u8> = (0..5_000_000u32).map(|i| (i % 251) as u8).collect();
+    let server = crate::testing::serve(move |_| crate::testing::MockResponse::ok(body.clone()));
+    let dir = crate::testing::temp_dir("download-checksum");
+    let file_path = dir.join("package.nupkg");
+    let file_path = file_path.to_str().unwrap();
+
+    let streamed = download_url_to_file(&format!("{}/package.nupkg", server.url), file_path, &DownloadOptions::default(), |_| {}).unwrap();
+    assert_eq!(streamed, hash::sha1_file(file_path).unwrap());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_download_file_survives_panicking_progress_callback() {
+    let server = crate::testing::serve(|_| crate::testing::MockResponse::ok(vec![7u8; 100]));
+    let dir = crate::testing::temp_dir("download-progress-panic");
+    let file_path = dir.join("package.nupkg");
+    let file_path = file_path.to_str().unwrap();
+
+    let result = download_url_to_file(&format!("{}/package.nupkg", server.url), file_path, &DownloadOptions::default(), |p| {
+        if p >= 50 {
+            panic!("progress callback failed");
+        }
+    });
+    assert!(result.unwrap_err().to_string().contains("progress callback panicked"));
+    assert!(!std::path::Path::new(file_path).exists());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_connections_per_host_are_limited() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let max_seen = Arc::new(AtomicUsize::new(0));
+    let (in_flight_clone, max_seen_clone) = (in_flight.clone(), max_seen.clone());
+    let server = crate::testing::serve(move |_| {
+        let now = in_flight_clone.fetch_add(1, Ordering::SeqCst) + 1;
+        max_seen_clone.fetch_max(now, Ordering::SeqCst);
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        in_flight_clone.fetch_sub(1, Ordering::SeqCst);
+        crate::testing::MockResponse::ok("test")
+    });
+
+    let options = DownloadOptions { max_connections_per_host: Some(2), ..Default::default() };
+    let handles: Vec<_> = (0..6)
+        .map(|i| {
+            let url = format!("{}/file{}", server.url, i);
+            let options = options.clone();
+            std::thread::spawn(move || download_url_as_bytes(&url, &options).unwrap())
+        })
+        .collect();
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), b"test");
+    }
+    assert_eq!(server.requests().len(), 6);
+    assert!(max_seen.load(Ordering::SeqCst) <= 2);
+}
+
+#[cfg(test)]
+fn serve_resumable(body: &'static [u8]) -> crate::testing::MockServer {
+    use crate::testing::MockResponse;
+    crate::testing::serve(move |req| {
+        let start = req.header("Range").and_then(|r| r.strip_prefix("bytes=")).and_then(|r| r.trim_end_matches('-').parse::<usize>().ok());
+        match start {
+            None => MockResponse::ok(body),
+            Some(start) if start >= body.len() => {
+                MockResponse::status(416).with_header("Content-Range", &format!("bytes */{}", body.len()))
+            }
+            Some(start) => MockResponse { status: 206, headers: vec![], body: body[start..].to_vec() }
+                .with_header("Content-Range", &format!("bytes {}-{}/{}", start, body.len() - 1, body.len())),
+        }
+    })
+}
+
+#[test]
+fn test_resume_appends_remaining_bytes() {
+    let server = serve_resumable(b"hello world");
+    let dir = crate::testing::temp_dir("download-resume-partial");
+    let file_path = dir.join("package.nupkg");
+    std::fs::write(&file_path, "hello").unwrap();
+    let options = DownloadOptions { resume_partial_downloads: true, ..Default::default() };
+
+    let checksum = download_url_to_file(&format!("{}/package.nupkg", server.url), file_path.to_str().unwrap(), &options, |_| {}).unwrap();
+    assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "hello world");
+    assert_eq!(checksum, hash::sha1_file(&file_path).unwrap());
+    assert_eq!(server.requests()[0].header("Range"), Some("bytes=5-"));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_download_resumes_after_connection_reset() {
+    use std::net::TcpListener;
+    let body: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://localhost:{}/package.nupkg", listener.local_addr().unwrap().port());
+    let ranges = Arc::new(Mutex::new(Vec::new()));
+
+    let server_body = body.clone();
+    let server_ranges = ranges.clone();
+    std::thread::spawn(move || {
+        for (i, stream) in listener.incoming().take(2).enumerate() {
+            let mut stream = stream.unwrap();
+            let mut buf = [0u8; 1024];
+            if i == 0 {
+                // leaving most of the request unread makes closing the socket send a reset, rather than a clean close
+                stream.read_exact(&mut buf[..1]).unwrap();
+                server_ranges.lock().unwrap().push(None);
+                let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", server_body.len());
+                stream.write_all(head.as_bytes()).unwrap();
+                stream.write_all(&server_body[..server_body.len() / 2]).unwrap();
+                std::thread::sleep(Duration::from_millis(200));
+                continue;
+            }
+            let mut request = Vec::new();
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            let request = String::from_utf8_lossy(&request).to_string();
+            let range = request.lines().find_map(|l| l.strip_prefix("Range: bytes=")).map(|r| r.trim_end_matches('-').to_owned());
+            server_ranges.lock().unwrap().push(range.clone());
+            let start: usize = range.unwrap().parse().unwrap();
+            let head = format!(
+                "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                server_body.len() - start,
+                start,
+                server_body.len() - 1,
+                server_body.len()
+            );
+            stream.write_all(head.as_bytes()).unwrap();
+            stream.write_all(&server_body[start..]).unwrap();
+        }
+    });
+
+    let dir = crate::testing::temp_dir("download-resume-reset");
+    let file_path = dir.join("package.nupkg");
+    let options = DownloadOptions { resume_partial_downloads: true, ..Default::default() };
+    let checksum = download_url_to_file(&url, file_path.to_str().unwrap(), &options, |_| {}).unwrap();
+    assert_eq!(std::fs::read(&file_path).unwrap(), body);
+    assert_eq!(checksum, hash::sha1_file(&file_path).unwrap());
+    assert_eq!(*ranges.lock().unwrap(), vec![None, Some((body.len() / 2).to_string())]);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_download_quota_refuses_downloads_once_used() {
+    let server = serve_resumable(b"hello world");
+    let dir = crate::testing::temp_dir("download-quota");
+    let quota = DownloadQuota::new(20);
+    let options = DownloadOptions { quota: Some(quota.clone()), resume_partial_downloads: true, ..Default::default() };
+    let url = format!("{}/file", server.url);
+
+    // resumed bytes which are already on disk are not counted again
+    let file = dir.join("resumed.bin");
+    std::fs::write(&file, "hello").unwrap();
+    download_url_to_file(&url, &file.to_string_lossy(), &options, |_| {}).unwrap();
+    assert_eq!(std::fs::read(&file).unwrap(), b"hello world");
+    assert_eq!((quota.used(), quota.remaining()), (6, 14));
+
+    let file = dir.join("full.bin");
+    download_url_to_file(&url, &file.to_string_lossy(), &options, |_| {}).unwrap();
+    assert_eq!(quota.used(), 17);
+
+    let err = download_url_to_file(&url, &dir.join("refused.bin").to_string_lossy(), &options, |_| {}).unwrap_err();
+    assert_eq!(err.downcast_ref::<VelopackError>(), Some(&VelopackError::QuotaExceeded { limit: 20, used: 17 }));
+    assert_eq!(quota.used(), 17);
+
+    quota.reset();
+    download_url_to_file(&url, &dir.join("after-reset.bin").to_string_lossy(), &options, |_| {}).unwrap();
+    assert_eq!(quota.used(), 11);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_resume_treats_full_size_partial_as_complete() {
+    let server = serve_resumable(b"hello world");
+    let dir = crate::testing::temp_dir("download-resume-complete");
+    let file_path = dir.join("package.nupkg");
+    std::fs::write(&file_path, "hello world").unwrap();
+    let options = DownloadOptions { resume_partial_downloads: true, ..Default::default() };
+
+    let mut reported = Vec::new();
+    let checksum =
+        download_url_to_file(&format!("{}/package.nupkg", server.url), file_path.to_str().unwrap(), &options, |p| reported.push(p))
+            .unwrap();
+    assert_eq!(checksum, hash::sha1_file(&file_path).unwrap());
+    assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "hello world");
+    assert_eq!(reported, vec![100]);
+    assert_eq!(server.requests().len(), 1);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_resume_restarts_when_partial_is_larger_than_remote() {
+    let server = serve_resumable(b"hello world");
+    let dir = crate::testing::temp_dir("download-resume-larger");
+    let file_path = dir.join("package.nupkg");
+    std::fs::write(&file_path, "hello world, and some stale bytes").unwrap();
+    let options = DownloadOptions { resume_partial_downloads: true, ..Default::default() };
+
+    download_url_to_file(&format!("{}/package.nupkg", server.url), file_path.to_str().unwrap(), &options, |_| {}).unwrap();
+    assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "hello world");
+    let ranges: Vec<Option<String>> = server.requests().iter().map(|r| r.header("Range").map(|h| h.to_owned())).collect();
+    assert_eq!(ranges, vec![Some("bytes=33-".to_owned()), None]);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_download_url_to_writer_streams_body() {
+    let server = crate::testing::serve(|_| crate::testing::MockResponse::ok("hello world"));
+    let mut output: Vec<u8> = Vec::new();
+    let mut reported = Vec::new();
+    let (len, checksum) =
+        download_url_to_writer(&format!("{}/package.nupkg", server.url), &mut output, &DownloadOptions::default(), |p| reported.push(p))
+            .unwrap();
+    assert_eq!(output, b"hello world");
+    assert_eq!(len, 11);
+    assert_eq!(checksum, "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed");
+    assert_eq!(reported, vec![100]);
+}
+
+#[test]
+fn test_download_without_content_length_reports_indeterminate_progress() {
+    let server = crate::testing::serve(|_| {
+        crate::testing::MockResponse::ok("5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n").with_header("Transfer-Encoding", "chunked")
+    });
+    let mut output: Vec<u8> = Vec::new();
+    let mut reported = Vec::new();
+    download_url_to_writer(&server.url, &mut output, &DownloadOptions::default(), |p| reported.push(p)).unwrap();
+    assert_eq!(output, b"hello world");
+    assert_eq!(reported, vec![INDETERMINATE_PROGRESS]);
+}
+
+#[test]
+fn test_feed_size_is_limited_without_content_length() {
+    let server = crate::testing::serve(|_| {
+        crate::testing::MockResponse::ok("5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n").with_header("Transfer-Encoding", "chunked")
+    });
+    let options = DownloadOptions { max_feed_size: Some(11), ..Default::default() };
+    assert_eq!(download_url_as_bytes(&server.url, &options).unwrap(), b"hello world");
+    let options = DownloadOptions { max_feed_size: Some(10), ..Default::default() };
+    let err = download_url_as_bytes(&server.url, &options).unwrap_err();
+    assert_eq!(err.downcast_ref::<VelopackError>(), Some(&VelopackError::FeedTooLarge { limit: 10 }));
+}
+
+#[test]
+fn test_redirect_to_disallowed_host_is_refused() {
+    use crate::testing::MockResponse;
+    let mirror = crate::testing::serve(|_| MockResponse::ok("mirror"));
+    let target = format!("http://127.0.0.1:{}/package.nupkg", mirror.url.rsplit(':').next().unwrap());
+    let server = crate::testing::serve(move |req| match req.path.as_str() {
+        "/package.nupkg" => MockResponse::status(302).with_header("Location", &target),
+        "/local" => MockResponse::status(302).with_header("Location", "/final"),
+        _ => MockResponse::ok("origin"),
+    });
+
+    let options = DownloadOptions { allowed_redirect_hosts: vec!["updates.example.com".to_owned()], ..Default::default() };
+    let err = download_url_as_bytes(&format!("{}/package.nupkg", server.url), &options).unwrap_err();
+    assert!(format!("{:?}", err).contains("not an allowed redirect host"), "{:?}", err);
+    assert!(mirror.requests().is_empty());
+    assert_eq!(download_url_as_bytes(&format!("{}/local", server.url), &options).unwrap(), b"origin");
+
+    let options = DownloadOptions { allowed_redirect_hosts: vec!["127.0.0.1".to_owned()], ..Default::default() };
+    assert_eq!(download_url_as_bytes(&format!("{}/package.nupkg", server.url), &options).unwrap(), b"mirror");
+}
+
+#[test]
+fn test_sensitive_headers_are_dropped_on_cross_host_redirect() {
+    use crate::testing::MockResponse;
+    let mirror = crate::testing::serve(|_| MockResponse::ok("mirror"));
+    let target = format!("http://127.0.0.1:{}/package.nupkg", mirror.url.rsplit(':').next().unwrap());
+    let server = crate::testing::serve(move |req| match req.path.as_str() {
+        "/package.nupkg" => MockResponse::status(302).with_header("Location", &target),
+        "/local" => MockResponse::status(302).with_header("Location", "/final"),
+        _ => MockResponse::ok("origin"),
+    });
+    let headers = vec![
+        RequestHeader { name: "X-Api-Key".to_owned(), value: "secret".to_owned(), sensitive: true },
+        RequestHeader { name: "X-Client".to_owned(), value: "velopack".to_owned(), sensitive: false },
+    ];
+    let options = DownloadOptions { headers, ..Default::default() };
+
+    assert_eq!(download_url_as_bytes(&format!("{}/package.nupkg", server.url), &options).unwrap(), b"mirror");
+    assert_eq!(server.requests()[0].header("X-Api-Key"), Some("secret"));
+    let redirected = &mirror.requests()[0];
+    assert_eq!(redirected.header("X-Api-Key"), None);
+    assert_eq!(redirected.header("X-Client"), Some("velopack"));
+
+    assert_eq!(download_url_as_bytes(&format!("{}/local", server.url), &options).unwrap(), b"origin");
+    assert_eq!(server.requests().last().unwrap().header("X-Api-Key"), Some("secret"));
+}
+
+#[test]
+fn test_set_cookie_domain_must_match_responding_host() {
+    let parse = |header: &str, host: &str| Cookie::parse_set_cookie(header, host).map(|c| (c.domain, c.secure));
+    assert_eq!(parse("a=1", "cdn.example.com"), Some(("cdn.example.com".to_owned(), false)));
+    assert_eq!(parse("a=1; Domain=.example.com; Secure", "cdn.example.com"), Some(("example.com".to_owned(), true)));
+    assert_eq!(parse("a=1; Domain=updates.example.org", "cdn.example.com"), None);
+    assert_eq!(parse("a=1; Domain=other.example.com", "cdn.example.com"), None);
+    assert_eq!(parse("a=1; Domain=com", "cdn.example.com"), None);
+    assert_eq!(parse("a=1; Domain=localhost", "localhost"), Some(("localhost".to_owned(), false)));
+    assert_eq!(parse("a=1; Domain=0.0.1", "127.0.0.1"), None);
+}
+
+#[test]
+fn test_server_cookies_are_scoped_by_domain_and_secure() {
+    use crate::testing::MockResponse;
+    let server = crate::testing::serve(|req| match req.path.as_str() {
+        "/package.nupkg" => MockResponse::status(302)
+            .with_header("Location", "/final")
+            .with_header("Set-Cookie", "plain=1; Path=/")
+            .with_header("Set-Cookie", "secure=1; Secure")
+            .with_header("Set-Cookie", "foreign=1; Domain=example.com"),
+        _ => MockResponse::ok("origin"),
+    });
+    let cookies = vec![Cookie { name: "sso".to_owned(), value: "abc".to_owned(), domain: "localhost".to_owned(), secure: false }];
+    let options = DownloadOptions { cookies, ..Default::default() };
+
+    assert_eq!(download_url_as_bytes(&format!("{}/package.nupkg", server.url), &options).unwrap(), b"origin");
+    assert_eq!(server.requests()[1].header("Cookie"), Some("sso=abc; plain=1"));
+}
+
+#[test]
+fn test_redirect_from_https_to_http_is_refused() {
+    use crate::testing::MockResponse;
+    let plain = crate::testing::serve(|_| MockResponse::ok("plain"));
+    let target = format!("{}/package.nupkg", plain.url);
+    let server = crate::testing::serve_tls(move |_| MockResponse::status(302).with_header("Location", &target));
+    let headers = vec![RequestHeader { name: "X-Api-Key".to_owned(), value: "secret".to_owned(), sensitive: true }];
+    let cookies = vec![Cookie { name: "sso".to_owned(), value: "abc".to_owned(), domain: "localhost".to_owned(), secure: false }];
+    let options =
+        DownloadOptions { headers, cookies, root_certificates: vec![crate::testing::fixture_ca_certificate()], ..Default::default() };
+
+    let err = download_url_as_bytes(&format!("{}/package.nupkg", server.url), &options).unwrap_err();
+    assert!(format!("{:?}", err).contains("Refusing to follow redirect from https"), "{:?}", err);
+    assert_eq!(server.requests().len(), 1);
+    assert!(plain.requests().is_empty());
+}
+
+#[test]
+fn test_redirect_count_is_capped() {
+    use crate::testing::MockResponse;
+    let server = crate::testing::serve(|req| {
+        let n: u32 = req.path.trim_start_matches('/').parse().unwrap_or(0);
+        MockResponse::status(302).with_header("Location", &format!("/{}", n + 1))
+    });
+    let options = DownloadOptions { max_redirects: Some(2), ..Default::default() };
+    assert!(download_url_as_bytes(&format!("{}/0", server.url), &options).is_err());
+    assert_eq!(server.requests().len(), 3);
+
+    let options = DownloadOptions { max_redirects: Some(2), allowed_redirect_hosts: vec!["localhost".to_owned()], ..Default::default() };
+    assert!(download_url_as_bytes(&format!("{}/0", server.url), &options).is_err());
+    assert_eq!(server.requests().len(), 6);
+}
+
+#[test]
+fn test_content_disposition_file_name() {
+    assert_eq!(content_disposition_file_name("attachment; filename=\"MyApp-1.0.0-full.nupkg\""), Some("MyApp-1.0.0-full.nupkg".to_owned()));
+    assert_eq!(
+        content_disposition_file_name("attachment; filename=a.nupkg; filename*=UTF-8''My%20App.nupkg"),
+        Some("My App.nupkg".to_owned())
+    );
+    assert_eq!(content_disposition_file_name("attachment; filename=\"../../evil.exe\""), Some("evil.exe".to_owned()));
+    assert_eq!(content_disposition_file_name("attachment; filename=\"..\""), None);
+    assert_eq!(content_disposition_file_name("inline"), None);
+}
+
+#[test]
+fn test_download_retries_dns_failures() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    let server = crate::testing::serve(|_| crate::testing::MockResponse::ok("hello"));
+    let port = server.url.rsplit(':').next().unwrap().to_owned();
+    let lookups = Arc::new(AtomicUsize::new(0));
+
+    let lookups_clone = lookups.clone();
+    let resolver: HostResolver = Arc::new(move |netloc: &str| {
+        assert_eq!(netloc, format!("updates.invalid:{}", port));
+        if lookups_clone.fetch_add(1, Ordering::SeqCst) == 0 {
+            return Err(io::Error::other("temporary failure in name resolution"));
+        }
+        Ok(vec![SocketAddr::from(([127, 0, 0, 1], port.parse().unwrap()))])
+    });
+    let options = DownloadOptions { resolver: Some(resolver), ..Default::default() };
+    let url = format!("http://updates.invalid:{}/package.nupkg", server.url.rsplit(':').next().unwrap());
+    assert_eq!(download_url_as_bytes(&url, &options).unwrap(), b"hello");
+    assert_eq!(lookups.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_connection_failures_are_not_dns_errors() {
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let err = get_download_agent(&DownloadOptions::default()).unwrap().get(&format!("http://127.0.0.1:{}", port)).call().unwrap_err();
+    assert!(!is_dns_error(&err));
+}
+
+#[cfg(test)]
+fn serve_ranges(body: Vec<u8>, supports_ranges: bool) -> crate::testing::MockServer {
+    use crate::testing::MockResponse;
+    crate::testing::serve(move |req| {
+        let range = req.header("Range").and_then(|r| r.strip_prefix("bytes=")).and_then(|r| r.split_once('-'));
+        match range {
+            Some((start, end)) if supports_ranges => {
+                let start: usize = start.parse().unwrap();
+                let end = end.parse::<usize>().unwrap_or(body.len() - 1).min(body.len() - 1);
+                MockResponse { status: 206, headers: vec![], body: body[start..=end].to_vec() }
+                    .with_header("Content-Range", &format!("bytes {}-{}/{}", start, end, body.len()))
+            }
+            _ => MockResponse::ok(body.clone()),
+        }
+    })
+}
+
+#[test]
+fn test_parallel_download_assembles_parts() {
+    let body: Vec<u8> = (0..1_000_003u32).map(|i| (i % 251) as u8).collect();
+    let server = serve_ranges(body.clone(), true);
+    let dir = crate::testing::temp_dir("download-parallel");
+    let file_path = dir.join("package.nupkg");
+    let options = DownloadOptions { parallel_download_parts: 4, parallel_download_threshold: 1000, ..Default::default() };
+
+    let mut reported = Vec::new();
+    let checksum = download_url_to_file(&server.url, file_path.to_str().unwrap(), &options, |p| reported.push(p)).unwrap();
+    assert_eq!(std::fs::read(&file_path).unwrap(), body);
+    assert_eq!(checksum, hash::sha1_file(&file_path).unwrap());
+    assert_eq!(reported.last(), Some(&100));
+
+    let mut ranges: Vec<String> = server.requests().iter().filter_map(|r| r.header("Range").map(|h| h.to_owned())).collect();
+    ranges.sort();
+    assert_eq!(ranges, vec!["bytes=0-0", "bytes=0-250000", "bytes=250001-500001", "bytes=500002-750002", "bytes=750003-1000002"]);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_failed_parallel_download_does_not_count_against_quota() {
+    use crate::testing::MockResponse;
+    let body: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+    let served = body.clone();
+    let server = crate::testing::serve(move |req| {
+        match req.header("Range").and_then(|r| r.strip_prefix("bytes=")).and_then(|r| r.split_once('-')) {
+            // the last part fails, after the others have been received
+            Some(("7500", _)) => {
+                std::thread::sleep(Duration::from_millis(200));
+                MockResponse::status(500)
+            }
+            Some((start, end)) => {
+                let (start, end): (usize, usize) = (start.parse().unwrap(), end.parse().unwrap());
+                MockResponse { status: 206, headers: vec![], body: served[start..=end].to_vec() }
+                    .with_header("Content-Range", &format!("bytes {}-{}/{}", start, end, served.len()))
+            }
+            None => MockResponse::ok(served.clone()),
+        }
+    });
+    let dir = crate::testing::temp_dir("download-parallel-quota");
+    let file_path = dir.join("package.nupkg");
+    let quota = DownloadQuota::new(15_000);
+    let options =
+        DownloadOptions { parallel_download_parts: 4, parallel_download_threshold: 1000, quota: Some(quota.clone()), ..Default::default() };
+
+    download_url_to_file(&server.url, file_path.to_str().unwrap(), &options, |_| {}).unwrap();
+    assert_eq!(std::fs::read(&file_path).unwrap(), body);
+    assert_eq!(quota.used(), 10_000);
+    assert_eq!(server.requests().last().unwrap().header("Range"), None);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_parallel_download_is_verified_against_checksum_headers() {
+    use crate::testing::MockResponse;
+    let body: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+    let sha256 = base64::engine::general_purpose::STANDARD.encode(hash::sha256(&body));
+    let served = body.clone();
+    // the corrupt checksum is only sent with partial responses, so the single stream fallback succeeds
+    let server = crate::testing::serve(move |req| {
+        let checksum = if req.path.starts_with("/corrupt") { "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=" } else { sha256.as_str() };
+        match req.header("Range").and_then(|r| r.strip_prefix("bytes=")).and_then(|r| r.split_once('-')) {
+            Some((start, end)) => {
+                let (start, end): (usize, usize) = (start.parse().unwrap(), end.parse().unwrap());
+                MockResponse { status: 206, headers: vec![], body: served[start..=end].to_vec() }
+                    .with_header("Content-Range", &format!("bytes {}-{}/{}", start, end, served.len()))
+                    .with_header("x-amz-checksum-sha256", checksum)
+            }
+            None => MockResponse::ok(served.clone()),
+        }
+    });
+    let dir = crate::testing::temp_dir("download-parallel-checksums");
+    let file_path = dir.join("package.nupkg");
+    let options = DownloadOptions { parallel_download_parts: 4, parallel_download_threshold: 1000, ..Default::default() };
+
+    download_url_to_file(&format!("{}/valid", server.url), file_path.to_str().unwrap(), &options, |_| {}).unwrap();
+    assert_eq!(std::fs::read(&file_path).unwrap(), body);
+    assert!(server.requests().iter().all(|r| r.header("Range").is_some()));
+
+    download_url_to_file(&format!("{}/corrupt", server.url), file_path.to_str().unwrap(), &options, |_| {}).unwrap();
+    assert_eq!(std::fs::read(&file_path).unwrap(), body);
+    assert_eq!(server.requests().last().unwrap().header("Range"), None);
+
+    let agent = get_download_agent(&options).unwrap();
+    let err = download_in_parallel_parts(&agent, &format!("{}/corrupt", server.url), file_path.to_str().unwrap(), &options, &mut |_| {})
+        .unwrap_err();
+    assert!(matches!(err.downcast_ref::<VelopackError>(), Some(VelopackError::ChecksumMismatch { .. })), "{}", err);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_parallel_download_falls_back_without_range_support() {
+    let body: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+    let server = serve_ranges(body.clone(), false);
+    let dir = crate::testing::temp_dir("download-parallel-fallback");
+    let file_path = dir.join("package.nupkg");
+    let options = DownloadOptions { parallel_download_parts: 4, parallel_download_threshold: 1000, ..Default::default() };
+
+    download_url_to_file(&server.url, file_path.to_str().unwrap(), &options, |_| {}).unwrap();
+    assert_eq!(std::fs::read(&file_path).unwrap(), body);
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].header("Range"), None);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_ip_stack_filters_address_families() {
+    let server = crate::testing::serve(|_| crate::testing::MockResponse::ok("hello"));
+    let port: u16 = server.url.rsplit(':').next().unwrap().parse().unwrap();
+    // the IPv6 address is reserved for discarding traffic (RFC 6666), so it never answers
+    let resolver: HostResolver =
+        Arc::new(move |_| Ok(vec![SocketAddr::from(([0x100, 0, 0, 0, 0, 0, 0, 1], port)), SocketAddr::from(([127, 0, 0, 1], port))]));
+    let url = format!("http://updates.invalid:{}/package.nupkg", port);
+
+    let options = DownloadOptions { resolver: Some(resolver.clone()), ip_stack: IpStack::Ipv4Only, ..Default::default() };
+    assert_eq!(download_url_as_bytes(&url, &options).unwrap(), b"hello");
+
+    let v4_only: HostResolver = Arc::new(move |_| Ok(vec![SocketAddr::from(([127, 0, 0, 1], port))]));
+    let options = DownloadOptions { resolver: Some(v4_only), ip_stack: IpStack::Ipv6Only, ..Default::default() };
+    assert!(download_url_as_bytes(&url, &options).unwrap_err().to_string().contains("no addresses for the selected IP stack"));
+}
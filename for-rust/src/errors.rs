@@ -0,0 +1,153 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Errors with a specific meaning that callers may wish to handle. These are returned wrapped in an `anyhow::Error`,
+/// and can be inspected with `err.downcast_ref::<VelopackError>()`.
+pub enum VelopackError {
+    /// The newest release offered by the feed is older than the lowest version that will be accepted
+    /// (by default, the currently installed version).
+    DowngradeBlocked {
+        /// The lowest version that would have been accepted.
+        floor: String,
+        /// The version offered by the feed.
+        offered: String,
+    },
+    /// The detached signature of a release feed file was missing, or did not match the contents of the feed.
+    FeedSignatureInvalid {
+        /// The name of the feed file which failed verification.
+        file_name: String,
+    },
+    /// The server closed the connection before sending the number of bytes it declared with `Content-Length`.
+    IncompleteDownload {
+        /// The number of bytes the server declared.
+        expected: u64,
+        /// The number of bytes actually received.
+        actual: u64,
+    },
+    /// The release feed contains packages for a different app than the one installed.
+    AppIdMismatch {
+        /// The id of the installed app.
+        expected: String,
+        /// The app id declared by the feed.
+        found: String,
+    },
+    /// The hash of a release feed file did not match the hash returned by the feed attestation provider.
+    FeedAttestationMismatch {
+        /// The name of the feed file which failed verification.
+        file_name: String,
+        /// The SHA-256 hash returned by the attestation provider (empty if it could not be retrieved).
+        expected: String,
+        /// The SHA-256 hash of the feed which was retrieved.
+        actual: String,
+    },
+    /// The length of a downloaded package does not match the size declared by the release feed.
+    SizeMismatch {
+        /// The size declared by the feed.
+        expected: u64,
+        /// The size of the downloaded file.
+        actual: u64,
+    },
+    /// The SHA1 checksum of a package on disk does not match the checksum declared by the release feed, or a download does
+    /// not match a checksum header sent by the server (see `DownloadOptions::ignore_checksum_headers`).
+    ChecksumMismatch {
+        /// The checksum declared by the feed.
+        expected: String,
+        /// The checksum of the file on disk.
+        actual: String,
+    },
+    /// The release feed requires a newer version of the Velopack client library than the one running, so the update can
+    /// not be applied safely. The app should prompt the user to reinstall it manually.
+    ClientTooOld {
+        /// The minimum client version declared by the feed.
+        required: String,
+        /// The version of the running client.
+        current: String,
+    },
+    /// The release feed has passed its declared expiry time, or was published longer ago than the maximum accepted age
+    /// (see `UpdateOptions::MaximumFeedAge`). This may mean the feed is being served by an abandoned mirror.
+    FeedStale {
+        /// The publish time declared by the feed (empty if it does not declare one).
+        published: String,
+        /// The expiry time declared by the feed (empty if it does not declare one).
+        expires: String,
+    },
+    /// The download was refused because it would exceed the configured download quota (see `sources::DownloadQuota`). The
+    /// download should be deferred until the quota is reset.
+    QuotaExceeded {
+        /// The number of bytes allowed by the quota.
+        limit: u64,
+        /// The number of bytes downloaded against the quota so far.
+        used: u64,
+    },
+    /// The download was deferred because a condition registered with `UpdateManager::with_download_condition` returned
+    /// false (eg. the device is running on battery). The download should be tried again later.
+    DeferredByPolicy {
+        /// The reason the condition was registered with (eg. "on AC power").
+        reason: String,
+    },
+    /// The release feed is larger than the maximum feed size, so it was not read into memory. Very large feeds should be
+    /// split into shards (see `VelopackFeedShard`), so that only the newest part needs to be downloaded.
+    FeedTooLarge {
+        /// The maximum feed size in bytes.
+        limit: u64,
+    },
+    /// The update source was found to be misconfigured by `UpdateSource::validate_config`.
+    InvalidConfiguration {
+        /// A description of each problem which was found.
+        problems: Vec<String>,
+    },
+}
+
+impl fmt::Display for VelopackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VelopackError::DowngradeBlocked { floor, offered } => {
+                write!(f, "Refusing to downgrade to version {} as it is lower than the accepted minimum of {}.", offered, floor)
+            }
+            VelopackError::FeedSignatureInvalid { file_name } => {
+                write!(f, "The signature of release feed '{}' is missing or invalid.", file_name)
+            }
+            VelopackError::IncompleteDownload { expected, actual } => {
+                write!(f, "The download ended after {} of {} bytes.", actual, expected)
+            }
+            VelopackError::AppIdMismatch { expected, found } => {
+                write!(f, "The release feed is for app '{}', but the installed app is '{}'.", found, expected)
+            }
+            VelopackError::FeedAttestationMismatch { file_name, expected, actual } => {
+                write!(f, "The release feed '{}' has a SHA-256 hash of {}, but its attestation declares '{}'.", file_name, actual, expected)
+            }
+            VelopackError::SizeMismatch { expected, actual } => {
+                write!(f, "The downloaded package is {} bytes, but the release feed declares {} bytes.", actual, expected)
+            }
+            VelopackError::ChecksumMismatch { expected, actual } => {
+                write!(f, "The package has a SHA1 checksum of {}, but the release feed declares {}.", actual, expected)
+            }
+            VelopackError::ClientTooOld { required, current } => {
+                write!(f, "The release feed requires client version {} or newer, but the running client is {}.", required, current)
+            }
+            VelopackError::FeedStale { published, expires } if published.is_empty() => {
+                write!(f, "The release feed expired at {}.", expires)
+            }
+            VelopackError::FeedStale { published, expires } if expires.is_empty() => {
+                write!(f, "The release feed was published at {}, which is older than the maximum accepted age.", published)
+            }
+            VelopackError::FeedStale { published, expires } => {
+                write!(f, "The release feed (published at {}, expiring at {}) is stale.", published, expires)
+            }
+            VelopackError::QuotaExceeded { limit, used } => {
+                write!(f, "The download would exceed the quota of {} bytes ({} bytes have already been downloaded).", limit, used)
+            }
+            VelopackError::DeferredByPolicy { reason } => {
+                write!(f, "The download was deferred because a condition was not met: {}.", reason)
+            }
+            VelopackError::FeedTooLarge { limit } => {
+                write!(f, "The release feed is larger than the maximum feed size of {} bytes. Consider splitting it into shards.", limit)
+            }
+            VelopackError::InvalidConfiguration { problems } => {
+                write!(f, "The update source is misconfigured: {}", problems.join("; "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for VelopackError {}
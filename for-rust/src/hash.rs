@@ -0,0 +1,139 @@
+use sha1::Digest;
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+/// Incremental SHA-256 hasher, used to verify certificates and downloaded packages.
+#[derive(Clone, Default)]
+pub struct Sha256(sha2::Sha256);
+
+impl Sha256 {
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub fn finish(self) -> [u8; 32] {
+        self.0.finalize().into()
+    }
+}
+
+/// Incremental SHA-1 hasher, used to verify downloaded packages against the checksums in the release feed.
+#[derive(Clone, Default)]
+pub struct Sha1(sha1::Sha1);
+
+impl Sha1 {
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub fn finish(self) -> [u8; 20] {
+        self.0.finalize().into()
+    }
+}
+
+/// Incremental MD5 hasher, used only to verify downloads against the `Content-MD5` header sent by some object stores.
+#[derive(Clone, Default)]
+pub struct Md5(md5::Md5);
+
+impl Md5 {
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub fn finish(self) -> [u8; 16] {
+        self.0.finalize().into()
+    }
+}
+
+/// Computes the lowercase hex SHA-1 checksum of the file at the specified path.
+pub fn sha1_file<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha1::default();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let len = file.read(&mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buffer[..len]);
+    }
+    Ok(to_hex(&hasher.finish()))
+}
+
+/// Copies the reader into the writer, returning the number of bytes copied and their lowercase hex SHA-1 checksum.
+pub fn copy_with_sha1<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> io::Result<(u64, String)> {
+    let mut hasher = Sha1::default();
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut total = 0;
+    loop {
+        let len = reader.read(&mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buffer[..len]);
+        writer.write_all(&buffer[..len])?;
+        total += len as u64;
+    }
+    Ok((total, to_hex(&hasher.finish())))
+}
+
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::default();
+    hasher.update(data);
+    hasher.finish()
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[test]
+fn test_sha256_known_vectors() {
+    assert_eq!(to_hex(&sha256(b"")), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    assert_eq!(to_hex(&sha256(b"abc")), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    assert_eq!(
+        to_hex(&sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+        "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+    );
+}
+
+#[test]
+fn test_sha256_incremental_matches_single_update() {
+    let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+    let mut hasher = Sha256::default();
+    for chunk in data.chunks(37) {
+        hasher.update(chunk);
+    }
+    assert_eq!(hasher.finish(), sha256(&data));
+}
+
+#[test]
+fn test_md5_known_vectors() {
+    let md5 = |data: &[u8]| {
+        let mut hasher = Md5::default();
+        for chunk in data.chunks(7) {
+            hasher.update(chunk);
+        }
+        to_hex(&hasher.finish())
+    };
+    assert_eq!(md5(b""), "d41d8cd98f00b204e9800998ecf8427e");
+    assert_eq!(md5(b"hello world"), "5eb63bbbe01eeed093cb22bb8f5acdc3");
+    assert_eq!(
+        md5(b"12345678901234567890123456789012345678901234567890123456789012345678901234567890"),
+        "57edf4a22be3c955ac49da2e2107b67a"
+    );
+}
+
+#[test]
+fn test_sha1_known_vectors() {
+    let sha1 = |data: &[u8]| {
+        let mut hasher = Sha1::default();
+        hasher.update(data);
+        to_hex(&hasher.finish())
+    };
+    assert_eq!(sha1(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    assert_eq!(sha1(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    assert_eq!(sha1(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"), "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+}
@@ -84,16 +84,25 @@
 mod app;
 mod bundle;
 mod download;
+mod errors;
+mod hash;
 mod manager;
 mod manifest;
 mod util;
 
+#[cfg(test)]
+mod testing;
+
 /// Locator provides some utility functions for locating the current app important paths (eg. path to packages, update binary, and so forth).
 pub mod locator;
+/// Loopback provides a minimal HTTP server for serving a local directory as an update feed in tests (requires the 'loopback' feature).
+#[cfg(any(test, feature = "loopback"))]
+pub mod loopback;
 /// Sources contains abstractions for custom update sources (eg. url, local file, github releases, etc).
 pub mod sources;
 
 pub use app::*;
+pub use errors::*;
 pub use manager::*;
 
 #[macro_use]
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use crate::{
     manifest::{self, Manifest},
@@ -20,6 +20,7 @@ pub struct VelopackLocator {
 }
 
 /// Default log location for Velopack code.
+#[allow(clippy::needless_return)]
 pub fn default_log_location() -> PathBuf {
     #[cfg(target_os = "windows")]
     {
@@ -148,11 +149,116 @@ pub fn auto_locate() -> Result<VelopackLocator> {
     })
 }
 
+/// Locates and reads the manifest of the currently running app, so that an update source can be called directly without
+/// assembling a manifest by hand (eg. `source.get_release_feed("stable", &auto_manifest()?)`). If the app is not installed,
+/// it will return an error.
+pub fn auto_manifest() -> Result<Manifest> {
+    auto_locate()
+        .map(|locator| locator.manifest)
+        .map_err(|e| anyhow!("Unable to read the app manifest, as this app does not appear to be installed by Velopack. ({})", e))
+}
+
+/// Reads the manifest of the app installed at the specified root directory (the directory containing Update.exe on Windows,
+/// the AppDir on Linux, or the '.app' bundle on macOS). If there is no app installed there, it will return an error.
+pub fn read_installed_manifest<P: AsRef<Path>>(root_app_dir: P) -> Result<Manifest> {
+    let manifest_path = installed_manifest_path(root_app_dir.as_ref());
+    if !manifest_path.exists() {
+        bail!(
+            "No app is installed at '{}', as the manifest '{}' does not exist.",
+            root_app_dir.as_ref().to_string_lossy(),
+            manifest_path.to_string_lossy()
+        );
+    }
+    read_current_manifest(&manifest_path)
+}
+
+/// Reads the channel declared by the manifest of the app installed at the specified root directory (see
+/// `read_installed_manifest`), for apps which do not store the channel they were installed from themselves. Returns None
+/// if the manifest does not declare a channel, or an error if there is no app installed there.
+pub fn read_installed_channel<P: AsRef<Path>>(root_app_dir: P) -> Result<Option<String>> {
+    Ok(read_installed_manifest(root_app_dir)?.installed_channel().map(|c| c.to_owned()))
+}
+
+/// The conventional location of the manifest within an installed app's root directory.
+#[allow(clippy::needless_return)]
+fn installed_manifest_path(root_app_dir: &Path) -> PathBuf {
+    #[cfg(target_os = "windows")]
+    {
+        return root_app_dir.join("current").join("sq.version");
+    }
+    #[cfg(target_os = "linux")]
+    {
+        return root_app_dir.join("usr").join("bin").join("sq.version");
+    }
+    #[cfg(target_os = "macos")]
+    {
+        return root_app_dir.join("Contents").join("MacOS").join("sq.version");
+    }
+}
+
 fn read_current_manifest(nuspec_path: &PathBuf) -> Result<Manifest> {
     if nuspec_path.exists() {
-        if let Ok(nuspec) = util::retry_io(|| std::fs::read_to_string(&nuspec_path)) {
-            return Ok(manifest::read_manifest_from_string(&nuspec)?);
+        if let Ok(nuspec) = util::retry_io(|| std::fs::read_to_string(nuspec_path)) {
+            return manifest::read_manifest_from_string(&nuspec);
         }
     }
     bail!("Unable to read nuspec file in current directory.")
 }
+
+#[test]
+fn test_read_installed_manifest_from_install_dir() {
+    let dir = crate::testing::temp_dir("locator-installed-manifest");
+    let err = read_installed_manifest(&dir).unwrap_err();
+    assert!(err.to_string().contains("No app is installed"));
+
+    let manifest_path = installed_manifest_path(&dir);
+    std::fs::create_dir_all(manifest_path.parent().unwrap()).unwrap();
+    let nuspec = r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://schemas.microsoft.com/packaging/2010/07/nuspec.xsd">
+  <metadata>
+    <id>MyApp</id>
+    <version>1.2.3</version>
+    <channel>beta</channel>
+  </metadata>
+</package>"#;
+    std::fs::write(&manifest_path, nuspec).unwrap();
+
+    let manifest = read_installed_manifest(&dir).unwrap();
+    assert_eq!(manifest.id, "MyApp");
+    assert_eq!(manifest.version, semver::Version::new(1, 2, 3));
+    assert_eq!(manifest.channel, "beta");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_read_installed_channel_from_manifest() {
+    let dir = crate::testing::temp_dir("locator-installed-channel");
+    assert!(read_installed_channel(&dir).is_err());
+
+    let manifest_path = installed_manifest_path(&dir);
+    std::fs::create_dir_all(manifest_path.parent().unwrap()).unwrap();
+    let nuspec = |channel: &str| {
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://schemas.microsoft.com/packaging/2010/07/nuspec.xsd">
+  <metadata>
+    <id>MyApp</id>
+    <version>1.2.3</version>{}
+  </metadata>
+</package>"#,
+            channel
+        )
+    };
+    std::fs::write(&manifest_path, nuspec("\n    <channel>beta</channel>")).unwrap();
+    assert_eq!(read_installed_channel(&dir).unwrap().as_deref(), Some("beta"));
+
+    std::fs::write(&manifest_path, nuspec("")).unwrap();
+    assert_eq!(read_installed_channel(&dir).unwrap(), None);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_auto_manifest_errors_when_not_installed() {
+    let err = auto_manifest().unwrap_err();
+    assert!(err.to_string().contains("does not appear to be installed"));
+}
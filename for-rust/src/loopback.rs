@@ -0,0 +1,142 @@
+use anyhow::Result;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use crate::util;
+
+/// A running loopback server. The server is shut down when this is dropped, or when `shutdown` is called.
+pub struct LoopbackServer {
+    url: String,
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl LoopbackServer {
+    /// The base URL of the served directory (eg. 'http://127.0.0.1:50123'), which can be passed to `HttpSource::new`.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Stops accepting new connections.
+    pub fn shutdown(self) {}
+}
+
+impl Drop for LoopbackServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // wake up the accept loop so it can observe the shutdown flag
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+/// Serves the files in the specified directory (eg. a folder containing 'releases.{channel}.json' and its packages)
+/// over HTTP on a random loopback port. Query strings are ignored, and paths which would leave the directory are rejected.
+pub fn serve_directory<P: AsRef<Path>>(directory: P) -> Result<LoopbackServer> {
+    let root = directory.as_ref().canonicalize()?;
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let shutdown = Arc::new(AtomicBool::new(false));
+
+    let shutdown_clone = shutdown.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            if shutdown_clone.load(Ordering::SeqCst) {
+                break;
+            }
+            let Ok(stream) = stream else { continue };
+            let root = root.clone();
+            thread::spawn(move || {
+                if let Err(e) = handle_connection(stream, &root) {
+                    debug!("Loopback server connection failed: {}", e);
+                }
+            });
+        }
+    });
+
+    info!("Serving directory '{}' on http://{}", directory.as_ref().display(), addr);
+    Ok(LoopbackServer { url: format!("http://{}", addr), addr, shutdown })
+}
+
+fn handle_connection(stream: TcpStream, root: &Path) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or_default();
+    let mut stream = stream;
+
+    if method != "GET" && method != "HEAD" {
+        return write_status(&mut stream, 405, "Method Not Allowed");
+    }
+
+    let file = match resolve_path(root, target) {
+        Some(path) if path.is_file() => File::open(path)?,
+        _ => return write_status(&mut stream, 404, "Not Found"),
+    };
+    let length = file.metadata()?.len();
+    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", length)?;
+    if method == "GET" {
+        io::copy(&mut io::BufReader::new(file), &mut stream)?;
+    }
+    stream.flush()
+}
+
+fn write_status(stream: &mut TcpStream, status: u16, reason: &str) -> io::Result<()> {
+    write!(stream, "HTTP/1.1 {} {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status, reason)?;
+    stream.flush()
+}
+
+/// Maps a request target to a file inside the root directory, or None if it would escape the root.
+fn resolve_path(root: &Path, target: &str) -> Option<PathBuf> {
+    let path = target.split(['?', '#']).next().unwrap_or_default();
+    let mut resolved = root.to_path_buf();
+    for segment in path.split('/').filter(|s| !s.is_empty()) {
+        let segment = util::percent_decode(segment);
+        if segment == "." || segment == ".." || segment.contains(['/', '\\']) {
+            return None;
+        }
+        resolved.push(segment);
+    }
+    Some(resolved)
+}
+
+#[test]
+fn test_http_source_downloads_from_loopback_server() {
+    use crate::sources::{HttpSource, UpdateSource};
+
+    let dir = crate::testing::temp_dir("loopback-server");
+    let feed =
+        r#"{"Assets":[{"PackageId":"MyApp","Version":"1.0.0+build","Type":"Full","FileName":"MyApp-1.0.0+build-full.nupkg","Size":4}]}"#;
+    std::fs::write(dir.join("releases.stable.json"), feed).unwrap();
+    std::fs::write(dir.join("MyApp-1.0.0+build-full.nupkg"), "test").unwrap();
+    std::fs::write(dir.join("secret.txt"), "secret").unwrap();
+
+    let server = serve_directory(dir.join(".")).unwrap();
+    let source = HttpSource::new(server.url());
+    let feed = source.get_release_feed("stable", &crate::testing::test_manifest("0.9.0", "stable")).unwrap();
+    let local_file = dir.join("local.nupkg");
+    source.download_release_entry(&feed.Assets[0], &local_file.to_string_lossy(), |_| {}).unwrap();
+    assert_eq!(std::fs::read_to_string(&local_file).unwrap(), "test");
+
+    assert!(resolve_path(&dir, "/%2E%2E/secret.txt").is_none());
+    assert!(crate::download::download_url_as_bytes(&format!("{}/missing.nupkg", server.url()), &Default::default()).is_err());
+    server.shutdown();
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -1,11 +1,21 @@
 use anyhow::Result;
 use semver::Version;
 use serde::{Deserialize, Serialize};
-use std::{fs, process::exit, process::Command as Process};
+use std::{
+    cmp::Ordering,
+    fs,
+    path::{Path, PathBuf},
+    process::exit,
+    process::Command as Process,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use crate::{
+    hash,
     locator::{self, VelopackLocator},
     sources::UpdateSource,
+    util, VelopackError,
 };
 
 #[cfg(target_os = "windows")]
@@ -23,6 +33,33 @@ use async_std::task::JoinHandle;
 pub struct VelopackAssetFeed {
     /// The list of assets in the (probably remote) update feed.
     pub Assets: Vec<VelopackAsset>,
+    /// If the feed has been split into several files, this is the list of shard files which contain the remaining assets.
+    /// Update sources resolve these automatically, and only retrieve the shards needed to find the latest release.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub Shards: Vec<VelopackFeedShard>,
+    /// The lowest version of the Velopack client library which is able to apply the releases in this feed. If this is
+    /// empty, any client is accepted. Older feeds may declare this as 'RuntimeMin'.
+    #[serde(alias = "RuntimeMin", skip_serializing_if = "String::is_empty")]
+    pub MinimumVersion: String,
+    /// The time this feed was generated, as an RFC 3339 timestamp (eg. '2024-05-01T12:00:00Z'). This is compared against
+    /// `UpdateOptions::MaximumFeedAge` to detect a feed which has stopped being updated.
+    #[serde(alias = "Timestamp", skip_serializing_if = "String::is_empty")]
+    pub Published: String,
+    /// The time after which this feed should no longer be trusted, as an RFC 3339 timestamp. If this is empty, the feed
+    /// does not expire.
+    #[serde(alias = "ExpiresAt", skip_serializing_if = "String::is_empty")]
+    pub Expires: String,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+/// A reference to a file containing part of a sharded release feed.
+pub struct VelopackFeedShard {
+    /// The name of the shard file, relative to the location of the feed index.
+    pub FileName: String,
+    /// The highest version of any asset in this shard. If this is empty, the shard will always be retrieved.
+    pub MaxVersion: String,
 }
 
 impl VelopackAssetFeed {
@@ -30,6 +67,350 @@ impl VelopackAssetFeed {
     pub fn find(&self, release_name: &str) -> Option<&VelopackAsset> {
         self.Assets.iter().find(|x| x.FileName.eq_ignore_ascii_case(release_name))
     }
+
+    /// Returns every asset in the feed for the specified version (eg. the full and delta packages, and any portable or
+    /// setup assets), in feed order. Versions are compared as semver where possible, ignoring a leading 'v', so 'v1.1.0'
+    /// matches assets with the version '1.1.0'.
+    pub fn get_assets_for_version(&self, version: &str) -> Vec<VelopackAsset> {
+        let parse = |v: &str| Version::parse(v.trim().trim_start_matches(['v', 'V'])).ok();
+        let wanted = parse(version);
+        self.Assets
+            .iter()
+            .filter(|a| match (&wanted, parse(&a.Version)) {
+                (Some(wanted), Some(v)) => wanted == &v,
+                _ => a.Version.trim().eq_ignore_ascii_case(version.trim()),
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Parses a legacy Squirrel 'RELEASES' file, where each line contains the SHA1 checksum, file name and size of a package
+    /// separated by whitespace, to help migrate from Squirrel. The package id, version and type are read from the file name
+    /// (eg. 'MyApp-1.0.0-full.nupkg'). If the file name is a url, it is added to the asset's `Urls`. Blank lines, trailing
+    /// whitespace, a byte order mark and '#' comments (including staged rollout percentages) are ignored.
+    pub fn from_legacy_releases(text: &str) -> Result<VelopackAssetFeed> {
+        let mut feed = VelopackAssetFeed::default();
+        for (i, line) in text.trim_start_matches('\u{feff}').lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [sha1, location, size] = fields[..] else {
+                bail!("Line {} of the RELEASES file does not contain a checksum, file name and size.", i + 1);
+            };
+            let size = size.parse::<u64>().map_err(|_| anyhow!("Line {} of the RELEASES file has an invalid size '{}'.", i + 1, size))?;
+            let file_name = location.rsplit('/').next().unwrap_or(location);
+            let stem = file_name.strip_suffix(".nupkg").unwrap_or(file_name);
+            let (stem, kind) = match stem.rsplit_once('-') {
+                Some((rest, kind)) if kind.eq_ignore_ascii_case("full") => (rest, "Full"),
+                Some((rest, kind)) if kind.eq_ignore_ascii_case("delta") => (rest, "Delta"),
+                _ => (stem, "Full"),
+            };
+            let version_start = stem.match_indices('-').map(|(i, _)| i + 1).find(|i| stem[*i..].starts_with(|c: char| c.is_ascii_digit()));
+            let Some(version_start) = version_start else {
+                bail!("Line {} of the RELEASES file has a file name without a version '{}'.", i + 1, file_name);
+            };
+            feed.Assets.push(VelopackAsset {
+                PackageId: stem[..version_start - 1].to_owned(),
+                Version: stem[version_start..].to_owned(),
+                Type: kind.to_owned(),
+                FileName: file_name.to_owned(),
+                SHA1: sha1.to_owned(),
+                Size: size,
+                Urls: if location.contains("://") { vec![location.to_owned()] } else { Vec::new() },
+                ..Default::default()
+            });
+        }
+        Ok(feed)
+    }
+
+    /// Merges several feeds (eg. the same channel retrieved from multiple mirrors) into a single de-duplicated feed.
+    /// Assets are considered duplicates if they share the same version, filename and SHA1 checksum. Feeds should be
+    /// provided in order of preference (eg. reachable mirrors first), as the first occurrence of a duplicate asset is kept,
+    /// with the download URLs of later duplicates appended to its `Urls` as fallback mirrors. Unresolved shards are kept
+    /// (once per file name), and are resolved relative to whichever source the merged feed is read from.
+    pub fn merge<I: IntoIterator<Item = VelopackAssetFeed>>(feeds: I) -> VelopackAssetFeed {
+        let mut merged = VelopackAssetFeed::default();
+        for feed in feeds {
+            let is_higher = match (Version::parse(&feed.MinimumVersion), Version::parse(&merged.MinimumVersion)) {
+                (Ok(v), Ok(current)) => v > current,
+                (Ok(_), Err(_)) => true,
+                _ => false,
+            };
+            if is_higher {
+                merged.MinimumVersion = feed.MinimumVersion;
+            }
+            // the merged feed is as fresh as the freshest feed it contains
+            let is_later = |v: &str, current: &str| match (util::parse_rfc3339(v), util::parse_rfc3339(current)) {
+                (Some(v), Some(current)) => v > current,
+                (Some(_), None) => true,
+                _ => false,
+            };
+            if is_later(&feed.Published, &merged.Published) {
+                merged.Published = feed.Published;
+            }
+            if is_later(&feed.Expires, &merged.Expires) {
+                merged.Expires = feed.Expires;
+            }
+            for shard in feed.Shards {
+                if !merged.Shards.iter().any(|x| x.FileName.eq_ignore_ascii_case(&shard.FileName)) {
+                    merged.Shards.push(shard);
+                }
+            }
+            for asset in feed.Assets {
+                match merged.Assets.iter_mut().find(|x| x.is_same_asset(&asset)) {
+                    Some(existing) => {
+                        for url in asset.Urls {
+                            if !existing.Urls.contains(&url) {
+                                existing.Urls.push(url);
+                            }
+                        }
+                    }
+                    None => merged.Assets.push(asset),
+                }
+            }
+        }
+        merged
+    }
+
+    /// Checks that the provided client version satisfies the minimum version declared by the feed, returning
+    /// `VelopackError::ClientTooOld` if it does not. Feeds which do not declare a minimum version accept any client.
+    pub fn check_client_version(&self, client_version: &Version) -> Result<()> {
+        if self.MinimumVersion.is_empty() {
+            return Ok(());
+        }
+        let required = Version::parse(&self.MinimumVersion)?;
+        if *client_version < required {
+            error!("Release feed requires client version {} or newer, but the running client is {}.", required, client_version);
+            return Err(VelopackError::ClientTooOld { required: required.to_string(), current: client_version.to_string() }.into());
+        }
+        Ok(())
+    }
+
+    /// Checks that the feed has not expired at the specified time, and if a maximum age is provided, that it was published
+    /// no longer ago than that. Returns `VelopackError::FeedStale` if either check fails. Timestamps which the feed does not
+    /// declare are not checked, and a timestamp which is not valid RFC 3339 returns an error.
+    pub fn check_freshness(&self, now: SystemTime, max_age: Option<Duration>) -> Result<()> {
+        let parse = |value: &str| util::parse_rfc3339(value).ok_or_else(|| anyhow!("Invalid timestamp '{}' in release feed.", value));
+        let stale = || VelopackError::FeedStale { published: self.Published.clone(), expires: self.Expires.clone() };
+        if !self.Expires.is_empty() && parse(&self.Expires)? <= now {
+            return Err(stale().into());
+        }
+        if let Some(max_age) = max_age {
+            if self.Published.is_empty() {
+                debug!("Release feed does not declare when it was published, so its age can not be checked.");
+            } else if parse(&self.Published)? + max_age < now {
+                return Err(stale().into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds the ordered list of delta packages needed to move from the installed version to the target version, so that
+    /// an external patch applier can run them. No patching is performed here. The full package of the installed version is
+    /// included as the base release if the feed contains it (it is not needed when patching the installed app in place).
+    /// Returns None if any release between the two versions is missing a delta package, as the chain can not be applied.
+    pub fn get_delta_plan(&self, installed_version: &Version, target_version: &Version) -> Option<DeltaPlan> {
+        if target_version <= installed_version {
+            return None;
+        }
+
+        let in_range = |asset: &&VelopackAsset, kind: &str| {
+            asset.Type.eq_ignore_ascii_case(kind)
+                && Version::parse(&asset.Version).map(|v| &v > installed_version && &v <= target_version).unwrap_or(false)
+        };
+        let mut deltas: Vec<VelopackAsset> = self.Assets.iter().filter(|a| in_range(a, "Delta")).cloned().collect();
+        deltas.sort_by_cached_key(|a| Version::parse(&a.Version).unwrap());
+        deltas.dedup_by(|a, b| a.Version == b.Version);
+
+        if !self.Assets.iter().filter(|a| in_range(a, "Full")).all(|full| deltas.iter().any(|d| d.Version == full.Version)) {
+            info!("Release feed is missing a delta package between {} and {}.", installed_version, target_version);
+            return None;
+        }
+        if deltas.last().map(|d| Version::parse(&d.Version).unwrap()).as_ref() != Some(target_version) {
+            return None;
+        }
+
+        let base = self
+            .Assets
+            .iter()
+            .find(|a| a.Type.eq_ignore_ascii_case("Full") && Version::parse(&a.Version).ok().as_ref() == Some(installed_version))
+            .cloned();
+        Some(DeltaPlan { BaseRelease: base, DeltasToTarget: deltas })
+    }
+
+    /// Chooses how to download the target version from the installed version: either the full package of the target
+    /// version, or the chain of delta packages from `get_delta_plan`. With `DownloadPreference::Smallest`, the total size
+    /// of the delta chain is compared against the size of the full package and the smaller is chosen (the full package
+    /// wins a tie, as it is simpler to apply). Returns None if the feed does not contain the package(s) needed by the
+    /// preference, eg. when deltas are forced but the delta chain is incomplete.
+    pub fn get_download_plan(
+        &self,
+        installed_version: &Version,
+        target_version: &Version,
+        preference: DownloadPreference,
+    ) -> Option<DownloadPlan> {
+        let full = || {
+            self.Assets
+                .iter()
+                .find(|a| a.Type.eq_ignore_ascii_case("Full") && Version::parse(&a.Version).ok().as_ref() == Some(target_version))
+                .cloned()
+                .map(DownloadPlan::Full)
+        };
+        let deltas = || self.get_delta_plan(installed_version, target_version);
+        match preference {
+            DownloadPreference::ForceFull => full(),
+            DownloadPreference::ForceDelta => deltas().map(DownloadPlan::Deltas),
+            DownloadPreference::Smallest => match (full(), deltas()) {
+                (Some(full), Some(deltas)) => {
+                    let deltas = DownloadPlan::Deltas(deltas);
+                    let (full_size, delta_size) = (full.download_size(), deltas.download_size());
+                    debug!("Delta chain to {} is {} bytes, and the full package is {} bytes.", target_version, delta_size, full_size);
+                    Some(if delta_size < full_size { deltas } else { full })
+                }
+                (full, deltas) => full.or(deltas.map(DownloadPlan::Deltas)),
+            },
+        }
+    }
+
+    /// Summarises how far behind the installed version is, for messages such as "You're 3 versions behind (120 MB)".
+    /// The download size is the total of the delta packages if a complete delta chain is available, or otherwise the size
+    /// of the latest full package. Returns None if there is no full release newer than the installed version.
+    pub fn get_update_summary(&self, installed_version: &Version) -> Option<UpdateSummary> {
+        let mut newer: Vec<(Version, &VelopackAsset)> = self
+            .Assets
+            .iter()
+            .filter(|a| a.Type.eq_ignore_ascii_case("Full"))
+            .filter_map(|a| Version::parse(&a.Version).ok().map(|v| (v, a)))
+            .filter(|(v, _)| v > installed_version)
+            .collect();
+        newer.sort_by(|(a, _), (b, _)| a.cmp(b));
+        newer.dedup_by(|(a, _), (b, _)| a == b);
+
+        let (latest_version, latest) = newer.last()?;
+        let download_size = match self.get_delta_plan(installed_version, latest_version) {
+            Some(plan) => plan.DeltasToTarget.iter().map(|d| d.Size).sum(),
+            None => latest.Size,
+        };
+        Some(UpdateSummary {
+            LatestVersion: latest_version.to_string(),
+            ReleasesBehind: newer.len(),
+            DownloadSize: download_size,
+            SkippedVersions: newer[..newer.len() - 1].iter().map(|(v, _)| v.to_string()).collect(),
+        })
+    }
+
+    /// Deletes the packages ('*.nupkg' files) in a directory (eg. a download cache) which do not belong to one of the latest
+    /// `keep_latest` versions in this feed, so older versions stop accumulating while recent rollback targets are kept.
+    /// Packages of the installed version are never deleted, even if they are no longer in the feed. If `secure` is set, each
+    /// package is overwritten before it is removed (see `UpdateOptions::SecureDelete`). Returns the paths of the deleted files.
+    pub fn prune_packages<P: AsRef<Path>>(
+        &self,
+        directory: P,
+        keep_latest: usize,
+        installed_version: &Version,
+        secure: bool,
+    ) -> Result<Vec<PathBuf>> {
+        let mut versions: Vec<Version> = self.Assets.iter().filter_map(|a| Version::parse(&a.Version).ok()).collect();
+        versions.sort_by(|a, b| b.cmp(a));
+        versions.dedup();
+        versions.truncate(keep_latest);
+
+        let is_kept =
+            |asset: &VelopackAsset| Version::parse(&asset.Version).is_ok_and(|v| &v == installed_version || versions.contains(&v));
+        let kept: Vec<String> = self.Assets.iter().filter(|a| is_kept(a)).map(|a| a.FileName.to_ascii_lowercase()).collect();
+        let installed = installed_version.to_string().to_ascii_lowercase();
+        let is_installed_name = |name: &str| name.contains(&format!("-{}-", installed)) || name.contains(&format!("-{}.", installed));
+
+        let mut deleted = Vec::new();
+        for entry in fs::read_dir(directory.as_ref())? {
+            let path = entry?.path();
+            let name = path.file_name().map(|n| n.to_string_lossy().to_ascii_lowercase()).unwrap_or_default();
+            if !path.is_file() || !name.ends_with(".nupkg") || kept.contains(&name) || is_installed_name(&name) {
+                continue;
+            }
+            info!("Pruning stale package: '{}'", path.to_string_lossy());
+            crate::util::retry_io(|| util::remove_file(&path, secure))?;
+            deleted.push(path);
+        }
+        deleted.sort();
+        Ok(deleted)
+    }
+
+    /// Returns true if any release newer than the installed version, up to and including the latest full release, is
+    /// marked as mandatory. In this case the app should apply the update rather than letting the user skip it.
+    pub fn is_update_mandatory(&self, installed_version: &Version) -> bool {
+        let latest =
+            self.Assets.iter().filter(|a| a.Type.eq_ignore_ascii_case("Full")).filter_map(|a| Version::parse(&a.Version).ok()).max();
+        let latest = match latest {
+            Some(v) if &v > installed_version => v,
+            _ => return false,
+        };
+        self.Assets
+            .iter()
+            .filter(|a| a.IsMandatory)
+            .filter_map(|a| Version::parse(&a.Version).ok())
+            .any(|v| &v > installed_version && v <= latest)
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+/// A summary of the releases between the installed version and the latest release, see `VelopackAssetFeed::get_update_summary`.
+pub struct UpdateSummary {
+    /// The latest available version.
+    pub LatestVersion: String,
+    /// The number of releases newer than the installed version, including the latest.
+    pub ReleasesBehind: usize,
+    /// The total number of bytes which need to be downloaded to reach the latest version.
+    pub DownloadSize: u64,
+    /// The versions released between the installed version and the latest version, which will be skipped over.
+    pub SkippedVersions: Vec<String>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+/// The packages needed to reach a target version by applying delta packages, see `VelopackAssetFeed::get_delta_plan`.
+pub struct DeltaPlan {
+    /// The full package of the installed version, which the deltas are applied on top of. This is None if the feed
+    /// no longer contains it, in which case the deltas can only be applied to the installed app in place.
+    pub BaseRelease: Option<VelopackAsset>,
+    /// The delta packages to apply, ordered from the oldest to the newest (the target version).
+    pub DeltasToTarget: Vec<VelopackAsset>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// Which packages `VelopackAssetFeed::get_download_plan` should choose to reach the target version.
+pub enum DownloadPreference {
+    /// Choose whichever of the delta chain or the full package is smaller to download.
+    #[default]
+    Smallest,
+    /// Always download the full package.
+    ForceFull,
+    /// Always download the delta chain, even if it is larger than the full package.
+    ForceDelta,
+}
+
+#[derive(Debug, Clone)]
+/// The packages chosen by `VelopackAssetFeed::get_download_plan`.
+pub enum DownloadPlan {
+    /// Download the full package of the target version.
+    Full(VelopackAsset),
+    /// Download and apply the delta packages to the installed version.
+    Deltas(DeltaPlan),
+}
+
+impl DownloadPlan {
+    /// Returns the total number of bytes which need to be downloaded for this plan.
+    pub fn download_size(&self) -> u64 {
+        match self {
+            DownloadPlan::Full(full) => full.Size,
+            DownloadPlan::Deltas(plan) => plan.DeltasToTarget.iter().map(|d| d.Size).sum(),
+        }
+    }
 }
 
 #[allow(non_snake_case)]
@@ -53,6 +434,135 @@ pub struct VelopackAsset {
     pub NotesMarkdown: String,
     /// The release notes in HTML format, transformed from Markdown when packaging the release. This may be an empty string.
     pub NotesHtml: String,
+    /// True if this release is mandatory (eg. a security fix), and users should not be allowed to skip it.
+    /// Feeds may also declare this as 'Mandatory' or 'Critical'.
+    #[serde(alias = "Mandatory", alias = "mandatory", alias = "Critical", alias = "critical", skip_serializing_if = "std::ops::Not::not")]
+    pub IsMandatory: bool,
+    /// Absolute URLs of mirrors hosting the update package of this release. Sources which download over http try each of
+    /// these in order, and then the location of the package in the source itself, until one succeeds.
+    #[serde(alias = "Mirrors", skip_serializing_if = "Vec::is_empty")]
+    pub Urls: Vec<String>,
+    /// Headers to send when downloading the update package of this release (eg. a per-file access token), in addition to
+    /// any headers configured on the update source. These replace source headers with the same name, and like sensitive
+    /// source headers, are not sent to another host if the download is redirected.
+    #[serde(skip_serializing_if = "std::collections::BTreeMap::is_empty")]
+    pub Headers: std::collections::BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The kind of file an asset refers to, see `VelopackAsset::kind`.
+pub enum AssetKind {
+    /// A full update package, containing the whole app.
+    Full,
+    /// A delta update package, which is applied on top of the previous release.
+    Delta,
+    /// A portable build of the app which runs without being installed (eg. a zip, or an AppImage on Linux).
+    Portable,
+    /// An installer for the app (eg. 'MyApp-win-Setup.exe').
+    Setup,
+    /// Any other type declared by the feed, or an empty string if the type could not be determined.
+    Other(String),
+}
+
+impl AssetKind {
+    /// Parses the type declared by a feed (eg. "Full" or "Delta"), ignoring case.
+    pub fn from_type(asset_type: &str) -> AssetKind {
+        match asset_type.trim().to_ascii_lowercase().as_str() {
+            "full" => AssetKind::Full,
+            "delta" => AssetKind::Delta,
+            "portable" => AssetKind::Portable,
+            "setup" | "installer" => AssetKind::Setup,
+            _ => AssetKind::Other(asset_type.trim().to_owned()),
+        }
+    }
+
+    /// Guesses the kind of an asset from the naming conventions used by `vpk` (eg. 'MyApp-1.0.0-full.nupkg' or
+    /// 'MyApp-win-Portable.zip'), returning None if the file name does not follow one of them.
+    pub fn from_file_name(file_name: &str) -> Option<AssetKind> {
+        let name = file_name.to_ascii_lowercase();
+        if name.ends_with("-full.nupkg") {
+            Some(AssetKind::Full)
+        } else if name.ends_with("-delta.nupkg") {
+            Some(AssetKind::Delta)
+        } else if name.ends_with("-portable.zip") || name.ends_with(".appimage") {
+            Some(AssetKind::Portable)
+        } else if ["-setup.exe", "-setup.msi", "-setup.pkg"].iter().any(|s| name.ends_with(s)) {
+            Some(AssetKind::Setup)
+        } else {
+            None
+        }
+    }
+}
+
+impl VelopackAsset {
+    /// Returns the kind of this asset, from the type declared by the feed or, if the feed does not declare one, from the
+    /// naming conventions of its file name. A type which is not recognised is returned as `AssetKind::Other`.
+    pub fn kind(&self) -> AssetKind {
+        if self.Type.trim().is_empty() {
+            return AssetKind::from_file_name(&self.FileName).unwrap_or(AssetKind::Other(String::new()));
+        }
+        AssetKind::from_type(&self.Type)
+    }
+
+    /// Returns true if the file at the specified path matches the SHA1 checksum of this asset. If the feed does
+    /// not provide a checksum, the file size is compared instead. Returns false if the file can not be verified.
+    pub(crate) fn matches_local_file<P: AsRef<Path>>(&self, path: P) -> bool {
+        let path = path.as_ref();
+        if !self.SHA1.is_empty() {
+            return hash::sha1_file(path).map(|h| h.eq_ignore_ascii_case(&self.SHA1)).unwrap_or(false);
+        }
+        if self.Size > 0 {
+            return path.metadata().map(|m| m.len() == self.Size).unwrap_or(false);
+        }
+        false
+    }
+
+    /// Checks that the length of a freshly downloaded file matches the size declared by the feed, returning
+    /// `VelopackError::SizeMismatch` if it does not. Assets which do not declare a size are not checked.
+    pub(crate) fn verify_downloaded_size<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        if self.Size == 0 {
+            return Ok(());
+        }
+        self.verify_streamed_size(path.as_ref().metadata()?.len())
+    }
+
+    /// Same as `verify_downloaded_size`, but for a download which was streamed elsewhere rather than written to a file.
+    pub(crate) fn verify_streamed_size(&self, actual: u64) -> Result<()> {
+        if self.Size == 0 {
+            return Ok(());
+        }
+        if actual != self.Size {
+            error!("Downloaded file for '{}' is {} bytes, but the feed declares {} bytes.", self.FileName, actual, self.Size);
+            return Err(VelopackError::SizeMismatch { expected: self.Size, actual }.into());
+        }
+        Ok(())
+    }
+
+    /// Checks that the SHA1 checksum of a file matches the checksum declared by the feed, returning
+    /// `VelopackError::ChecksumMismatch` if it does not. Assets which do not declare a checksum are not checked.
+    pub(crate) fn verify_checksum<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        if self.SHA1.is_empty() {
+            return Ok(());
+        }
+        self.verify_computed_checksum(&hash::sha1_file(path)?)
+    }
+
+    /// Same as `verify_checksum`, but compares a checksum which has already been computed (eg. while downloading).
+    pub(crate) fn verify_computed_checksum(&self, actual: &str) -> Result<()> {
+        if self.SHA1.is_empty() {
+            return Ok(());
+        }
+        let actual = actual.to_ascii_lowercase();
+        if !actual.eq_ignore_ascii_case(&self.SHA1) {
+            error!("File for '{}' has a SHA1 checksum of {}, but the feed declares {}.", self.FileName, actual, self.SHA1);
+            return Err(VelopackError::ChecksumMismatch { expected: self.SHA1.clone(), actual }.into());
+        }
+        Ok(())
+    }
+
+    fn is_same_asset(&self, other: &VelopackAsset) -> bool {
+        self.Version == other.Version && self.FileName.eq_ignore_ascii_case(&other.FileName) && self.SHA1.eq_ignore_ascii_case(&other.SHA1)
+    }
 }
 
 #[allow(non_snake_case)]
@@ -74,7 +584,7 @@ impl AsRef<VelopackAsset> for UpdateInfo {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 #[allow(non_snake_case)]
 /// Options to customise the behaviour of UpdateManager.
 pub struct UpdateOptions {
@@ -86,11 +596,156 @@ pub struct UpdateOptions {
     /// **This option should usually be left None**. <br/>
     /// Overrides the default channel used to fetch updates.
     /// The default channel will be whatever channel was specified on the command line when building this release.
-    /// For example, if the current release was packaged with '--channel beta', then the default channel will be 'beta'.
+    /// For example, if the current release was packaged with '--channel beta', then the default channel will be 'beta',
+    /// unless the source has been configured with a default channel of its own (see `UpdateSource::resolve_channel`).
     /// This allows users to automatically receive updates from the same channel they installed from. This options
     /// allows you to explicitly switch channels, for example if the user wished to switch back to the 'stable' channel
     /// without having to reinstall the application.
     pub ExplicitChannel: Option<String>,
+    /// The lowest version that UpdateManager will accept from the release feed. If the newest release in the feed is
+    /// lower than this, checking for updates will fail with `VelopackError::DowngradeBlocked`, which protects against
+    /// a rolled-back or malicious feed. When this is None, the floor is the currently installed version, unless
+    /// AllowVersionDowngrade is enabled in which case no floor is applied.
+    pub MinimumAcceptedVersion: Option<String>,
+    /// When enabled, checking for updates will fail with `VelopackError::AppIdMismatch` if any package in the release
+    /// feed belongs to a different app id than the one installed. This catches a source which has been accidentally
+    /// pointed at another application's releases, before any unrelated package is downloaded.
+    pub VerifyFeedAppId: bool,
+    /// How to choose between several assets in the feed which have the same version. See `AssetSelectionPolicy`.
+    pub AssetSelection: AssetSelectionPolicy,
+    /// When enabled, packages whose file name contains the runtime identifier of another platform (eg. 'osx-x64' when
+    /// running on 'osx-arm64') are ignored, and packages for the running platform are preferred. The running platform
+    /// is detected with `get_host_rid`.
+    pub MatchHostRid: bool,
+    /// Selects packages for this runtime identifier (eg. 'win-arm64') instead of the detected platform, in the same way
+    /// as `MatchHostRid`. This takes effect even if `MatchHostRid` is not enabled.
+    pub ForceRid: Option<String>,
+    /// The maximum age of a release feed, measured from the 'Published' time it declares. Feeds which are older than this,
+    /// or which have passed their declared 'Expires' time, are considered stale (see `RejectStaleFeeds`). Feeds which do
+    /// not declare a publish time are not checked.
+    pub MaximumFeedAge: Option<Duration>,
+    /// When enabled, checking for updates will fail with `VelopackError::FeedStale` if the release feed is stale. Otherwise
+    /// a warning is logged and the feed is used anyway.
+    pub RejectStaleFeeds: bool,
+    /// When enabled, old packages cleaned up after downloading an update are overwritten with zeros before they are removed,
+    /// rather than only being unlinked. This is best-effort: on copy-on-write or journaling filesystems, SSDs, or disks
+    /// which are backed up or snapshotted, the original contents may still be recoverable. Partially downloaded files, and
+    /// temporary or cached packages removed by the source, are handled by the source (eg. `HttpSource::with_secure_delete`).
+    pub SecureDelete: bool,
+    /// The client version compared against the 'MinimumVersion' declared by the release feed (eg. the version of the
+    /// language binding which embeds this library). When this is None, the version of this crate is used, unless it is a
+    /// local development build ('0.0.0-local'), in which case the feed's minimum version is not checked.
+    pub ClientVersion: Option<String>,
+}
+
+/// The runtime identifiers which may appear in package file names of a multi-architecture feed.
+const KNOWN_RIDS: [&str; 9] =
+    ["win-x64", "win-x86", "win-arm64", "osx-x64", "osx-arm64", "linux-x64", "linux-x86", "linux-arm64", "linux-arm"];
+
+/// Returns the runtime identifier of the running platform (eg. 'win-x64', 'osx-arm64' or 'linux-arm64'). On macOS, an
+/// x64 process running under Rosetta reports 'osx-arm64', so that it updates to the native package.
+pub fn get_host_rid() -> String {
+    host_rid_for(std::env::consts::OS, std::env::consts::ARCH, is_translated_process())
+}
+
+fn host_rid_for(os: &str, arch: &str, translated_to_arm64: bool) -> String {
+    let os = match os {
+        "windows" => "win",
+        "macos" => "osx",
+        other => other,
+    };
+    let arch = match arch {
+        _ if translated_to_arm64 => "arm64",
+        "x86_64" => "x64",
+        "aarch64" => "arm64",
+        other => other,
+    };
+    format!("{}-{}", os, arch)
+}
+
+fn is_translated_process() -> bool {
+    #[cfg(target_os = "macos")]
+    if let Ok(output) = Process::new("sysctl").args(["-n", "sysctl.proc_translated"]).output() {
+        return String::from_utf8_lossy(&output.stdout).trim() == "1";
+    }
+    false
+}
+
+/// Returns the runtime identifier contained in a package file name (eg. 'MyApp-1.0.0-osx-arm64-full.nupkg'), if any.
+fn get_file_name_rid(file_name: &str) -> Option<&'static str> {
+    let name = file_name.to_ascii_lowercase();
+    KNOWN_RIDS.iter().copied().find(|rid| name.contains(&format!("-{}-", rid)) || name.contains(&format!("-{}.", rid)))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(non_snake_case)]
+/// The tie-break rules used when a feed contains several assets with the same version (eg. a full and a delta package,
+/// or packages for several runtimes). The rules are applied in the order below, and if all of them are equal the assets
+/// are ordered by runtime identifier, file name, package id, type, checksum and size. This is a total order, so the
+/// selection never depends on the order of the feed (eg. a feed merged from several mirrors).
+pub struct AssetSelectionPolicy {
+    /// Prefer full packages over delta packages. Defaults to true.
+    pub PreferFull: bool,
+    /// Prefer assets whose file name contains this runtime identifier (eg. 'win-x64'). Defaults to None.
+    pub PreferredRid: Option<String>,
+    /// Prefer the smallest asset. Defaults to true.
+    pub PreferSmallest: bool,
+}
+
+impl Default for AssetSelectionPolicy {
+    fn default() -> Self {
+        AssetSelectionPolicy { PreferFull: true, PreferredRid: None, PreferSmallest: true }
+    }
+}
+
+impl AssetSelectionPolicy {
+    /// Compares two assets with the same version, returning `Ordering::Less` if the first asset should be chosen.
+    /// This only returns `Ordering::Equal` if the assets are identical in every field used for selection.
+    pub fn compare(&self, a: &VelopackAsset, b: &VelopackAsset) -> Ordering {
+        let is_full = |x: &VelopackAsset| x.Type.eq_ignore_ascii_case("Full");
+        let has_rid = |x: &VelopackAsset| match &self.PreferredRid {
+            Some(rid) => x.FileName.to_ascii_lowercase().contains(&rid.to_ascii_lowercase()),
+            None => false,
+        };
+        let mut ordering = Ordering::Equal;
+        if self.PreferFull {
+            ordering = ordering.then(is_full(b).cmp(&is_full(a)));
+        }
+        ordering = ordering.then(has_rid(b).cmp(&has_rid(a)));
+        if self.PreferSmallest {
+            ordering = ordering.then(a.Size.cmp(&b.Size));
+        }
+        ordering
+            .then_with(|| get_file_name_rid(&a.FileName).cmp(&get_file_name_rid(&b.FileName)))
+            .then_with(|| a.FileName.cmp(&b.FileName))
+            .then_with(|| a.PackageId.cmp(&b.PackageId))
+            .then_with(|| a.Type.cmp(&b.Type))
+            .then_with(|| a.SHA1.cmp(&b.SHA1))
+            .then_with(|| a.Size.cmp(&b.Size))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The result of `UpdateManager::download_updates_prefer_delta`.
+pub enum DeltaUpdateOutcome {
+    /// The delta packages were downloaded and applied successfully by the callback.
+    DeltasApplied,
+    /// The delta packages could not be used, and the full package was downloaded to this path instead.
+    FullPackage(std::path::PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The phases an update operation moves through, reported to the callback registered with `UpdateManager::on_phase_changed`.
+/// This is reported in addition to the percentage progress, so that a UI can label what the progress refers to.
+pub enum UpdatePhase {
+    /// The release feed is being retrieved from the update source.
+    FetchingFeed,
+    /// The release feed is being searched for the best available release.
+    SelectingRelease,
+    /// The selected package is being downloaded to the local packages directory.
+    Downloading,
+    /// The downloaded packages are being checked against the size and SHA1 checksum declared by the release feed.
+    Verifying,
 }
 
 /// Provides functionality for checking for updates, downloading updates, and applying updates to the current application.
@@ -100,17 +755,42 @@ where
 {
     allow_version_downgrade: bool,
     explicit_channel: Option<String>,
+    minimum_accepted_version: Option<String>,
+    client_version: Option<String>,
+    verify_feed_app_id: bool,
+    maximum_feed_age: Option<Duration>,
+    reject_stale_feeds: bool,
+    secure_delete: bool,
+    asset_selection: AssetSelectionPolicy,
+    target_rid: Option<String>,
     source: T,
     paths: VelopackLocator,
+    phase_callback: Option<Arc<dyn Fn(UpdatePhase) + Send + Sync>>,
+    download_conditions: Vec<(String, DownloadCondition)>,
+    release_comparator: Option<ReleaseComparator>,
 }
 
+type DownloadCondition = Arc<dyn Fn() -> bool + Send + Sync>;
+type ReleaseComparator = Arc<dyn Fn(&VelopackAsset, &VelopackAsset) -> Ordering + Send + Sync>;
+
 impl<T: UpdateSource> Clone for UpdateManager<T> {
     fn clone(&self) -> Self {
         UpdateManager {
             allow_version_downgrade: self.allow_version_downgrade,
             explicit_channel: self.explicit_channel.clone(),
+            minimum_accepted_version: self.minimum_accepted_version.clone(),
+            client_version: self.client_version.clone(),
+            verify_feed_app_id: self.verify_feed_app_id,
+            maximum_feed_age: self.maximum_feed_age,
+            reject_stale_feeds: self.reject_stale_feeds,
+            secure_delete: self.secure_delete,
+            asset_selection: self.asset_selection.clone(),
+            target_rid: self.target_rid.clone(),
             source: self.source.clone(),
             paths: self.paths.clone(),
+            phase_callback: self.phase_callback.clone(),
+            download_conditions: self.download_conditions.clone(),
+            release_comparator: self.release_comparator.clone(),
         }
     }
 }
@@ -149,21 +829,134 @@ impl<T: UpdateSource> UpdateManager<T> {
     /// let um = UpdateManager::new(source, None);
     /// ```
     pub fn new(source: T, options: Option<UpdateOptions>) -> Result<UpdateManager<T>> {
-        Ok(UpdateManager {
-            paths: locator::auto_locate()?,
+        Ok(Self::new_with_locator(source, options, locator::auto_locate()?))
+    }
+
+    /// Create a new UpdateManager instance using the specified UpdateSource and a pre-located set of app paths,
+    /// instead of locating the currently installed application automatically.
+    pub fn new_with_locator(source: T, options: Option<UpdateOptions>, paths: VelopackLocator) -> UpdateManager<T> {
+        UpdateManager {
+            paths,
             allow_version_downgrade: options.as_ref().map(|f| f.AllowVersionDowngrade).unwrap_or(false),
             explicit_channel: options.as_ref().map(|f| f.ExplicitChannel.clone()).unwrap_or(None),
+            minimum_accepted_version: options.as_ref().map(|f| f.MinimumAcceptedVersion.clone()).unwrap_or(None),
+            client_version: options.as_ref().and_then(|f| f.ClientVersion.clone()),
+            verify_feed_app_id: options.as_ref().map(|f| f.VerifyFeedAppId).unwrap_or(false),
+            maximum_feed_age: options.as_ref().and_then(|f| f.MaximumFeedAge),
+            reject_stale_feeds: options.as_ref().map(|f| f.RejectStaleFeeds).unwrap_or(false),
+            secure_delete: options.as_ref().map(|f| f.SecureDelete).unwrap_or(false),
+            asset_selection: options.as_ref().map(|f| f.AssetSelection.clone()).unwrap_or_default(),
+            target_rid: options.as_ref().and_then(|f| match &f.ForceRid {
+                Some(rid) => Some(rid.to_ascii_lowercase()),
+                None if f.MatchHostRid => Some(get_host_rid()),
+                None => None,
+            }),
             source,
-        })
+            phase_callback: None,
+            download_conditions: Vec::new(),
+            release_comparator: None,
+        }
+    }
+
+    /// Registers a callback which is invoked each time an update operation moves to a new UpdatePhase
+    /// (eg. from fetching the release feed to downloading the package).
+    pub fn on_phase_changed<F: Fn(UpdatePhase) + Send + Sync + 'static>(mut self, callback: F) -> Self {
+        self.phase_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Registers a condition which is checked before each download (eg. that the device is on AC power, or that memory
+    /// pressure is low). If it returns false, the download is deferred with `VelopackError::DeferredByPolicy` and the
+    /// provided reason. UpdateManager does not query the device state itself, so the condition should do so.
+    pub fn with_download_condition<F: Fn() -> bool + Send + Sync + 'static>(mut self, reason: &str, condition: F) -> Self {
+        self.download_conditions.push((reason.to_owned(), Arc::new(condition)));
+        self
+    }
+
+    /// Replaces how full releases in the feed are ordered from newest to oldest, which by default compares their semver
+    /// versions. The comparator should return `Ordering::Greater` if the first asset is newer (eg. by an internal build
+    /// number). Releases for other platforms are still filtered out, and ties are broken by the asset selection policy.
+    /// Only the choice between releases in the feed is affected: the chosen release is still an update only if its
+    /// version is higher than the installed version (or a downgrade, if downgrades are allowed).
+    pub fn with_release_comparator<F>(mut self, comparator: F) -> Self
+    where
+        F: Fn(&VelopackAsset, &VelopackAsset) -> Ordering + Send + Sync + 'static,
+    {
+        self.release_comparator = Some(Arc::new(comparator));
+        self
+    }
+
+    fn check_download_conditions(&self) -> Result<()> {
+        if let Some((reason, _)) = self.download_conditions.iter().find(|(_, condition)| !condition()) {
+            info!("Deferring download, as a download condition was not met: {}.", reason);
+            return Err(VelopackError::DeferredByPolicy { reason: reason.clone() }.into());
+        }
+        Ok(())
+    }
+
+    fn report_phase(&self, phase: UpdatePhase) {
+        debug!("Update phase changed: {:?}", phase);
+        if let Some(callback) = &self.phase_callback {
+            callback(phase);
+        }
     }
 
     fn get_practical_channel(&self) -> String {
-        let channel = self.explicit_channel.as_deref();
-        let mut channel = channel.unwrap_or(&self.paths.manifest.channel).to_string();
-        if channel.is_empty() {
-            channel = get_default_channel();
+        self.source.resolve_channel(self.explicit_channel.as_deref(), &self.paths.manifest)
+    }
+
+    /// Returns the full releases in the feed which are suitable for this platform, ordered from the most preferred (the
+    /// newest version, with ties broken by the asset selection policy) to the least.
+    fn rank_full_releases(&self, assets: Vec<VelopackAsset>) -> Vec<(Version, VelopackAsset)> {
+        let mut asset_selection = self.asset_selection.clone();
+        if let Some(rid) = &self.target_rid {
+            debug!("Selecting packages for runtime {}.", rid);
+            asset_selection.PreferredRid.get_or_insert_with(|| rid.clone());
+        }
+        let is_other_rid = |asset: &VelopackAsset| match (&self.target_rid, get_file_name_rid(&asset.FileName)) {
+            (Some(target), Some(rid)) => !rid.eq_ignore_ascii_case(target),
+            _ => false,
+        };
+
+        let mut releases = Vec::new();
+        for asset in assets {
+            if let Ok(sv) = Version::parse(&asset.Version) {
+                if asset.Type.eq_ignore_ascii_case("Full") && is_other_rid(&asset) {
+                    debug!("Skipping release for another runtime: {}.", asset.FileName);
+                } else if asset.Type.eq_ignore_ascii_case("Full") {
+                    debug!("Found full release: {} ({}).", asset.FileName, sv.to_string());
+                    releases.push((sv, asset));
+                }
+            }
+        }
+        let newest_first = |(va, a): &(Version, VelopackAsset), (vb, b): &(Version, VelopackAsset)| match &self.release_comparator {
+            Some(comparator) => comparator(b, a),
+            None => vb.cmp(va),
+        };
+        releases.sort_by(|a, b| newest_first(a, b).then_with(|| asset_selection.compare(&a.1, &b.1)));
+        releases
+    }
+
+    fn get_version_floor(&self) -> Result<Option<Version>> {
+        if let Some(floor) = &self.minimum_accepted_version {
+            return Ok(Some(Version::parse(floor)?));
+        }
+        if self.allow_version_downgrade {
+            return Ok(None);
+        }
+        Ok(Some(self.paths.manifest.version.clone()))
+    }
+
+    fn get_client_version(&self) -> Result<Option<Version>> {
+        if let Some(version) = &self.client_version {
+            return Ok(Some(Version::parse(version)?));
+        }
+        let crate_version = Version::parse(env!("CARGO_PKG_VERSION"))?;
+        if crate_version.pre.as_str() == "local" {
+            debug!("Not checking the minimum client version of the feed, as this is a local build ({}).", crate_version);
+            return Ok(None);
         }
-        channel
+        Ok(Some(crate_version))
     }
 
     /// The currently installed app version when you created your release.
@@ -189,12 +982,39 @@ impl<T: UpdateSource> UpdateManager<T> {
 
     /// Checks for updates, returning None if there are none available. If there are updates available, this method will return an
     /// UpdateInfo object containing the latest available release, and any delta updates that can be applied if they are available.
+    /// If the latest available release is lower than the accepted minimum version (see `UpdateOptions::MinimumAcceptedVersion`),
+    /// this will return a `VelopackError::DowngradeBlocked` error. If the feed requires a newer client than the one running
+    /// (see `UpdateOptions::ClientVersion`), this will return a `VelopackError::ClientTooOld` error. If the feed is stale and
+    /// `UpdateOptions::RejectStaleFeeds` is enabled, this will return a `VelopackError::FeedStale` error.
     pub fn check_for_updates(&self) -> Result<Option<UpdateInfo>> {
         let allow_downgrade = self.allow_version_downgrade;
         let app = &self.paths.manifest;
+        self.report_phase(UpdatePhase::FetchingFeed);
         let feed = self.get_release_feed()?;
+        if let Some(client_version) = self.get_client_version()? {
+            feed.check_client_version(&client_version)?;
+        }
+        if let Err(e) = feed.check_freshness(SystemTime::now(), self.maximum_feed_age) {
+            if self.reject_stale_feeds {
+                error!("Release feed failed the staleness check: {}", e);
+                return Err(e);
+            }
+            warn!("Release feed failed the staleness check, but will be used anyway: {}", e);
+        }
         let assets = feed.Assets;
 
+        if self.verify_feed_app_id {
+            if let Some(other) = assets.iter().find(|a| !a.PackageId.eq_ignore_ascii_case(&app.id)) {
+                error!(
+                    "Release feed contains package '{}' for app '{}', but the installed app is '{}'.",
+                    other.FileName, other.PackageId, app.id
+                );
+                return Err(VelopackError::AppIdMismatch { expected: app.id.clone(), found: other.PackageId.clone() }.into());
+            }
+        }
+
+        self.report_phase(UpdatePhase::SelectingRelease);
+
         let practical_channel = self.get_practical_channel();
         let is_non_default_channel = practical_channel != app.channel;
 
@@ -202,29 +1022,19 @@ impl<T: UpdateSource> UpdateManager<T> {
             bail!("Zero assets found in releases feed.");
         }
 
-        let mut latest: Option<VelopackAsset> = None;
-        let mut latest_version: Version = Version::parse("0.0.0")?;
-        for asset in assets {
-            if let Ok(sv) = Version::parse(&asset.Version) {
-                if asset.Type.eq_ignore_ascii_case("Full") {
-                    debug!("Found full release: {} ({}).", asset.FileName, sv.to_string());
-                    if latest.is_none() || (sv > latest_version) {
-                        latest = Some(asset);
-                        latest_version = sv;
-                    }
-                }
-            }
-        }
-
-        if latest.is_none() {
+        let Some((remote_version, remote_asset)) = self.rank_full_releases(assets).into_iter().next() else {
             bail!("No valid full releases found in feed.");
-        }
-
-        let remote_version = latest_version;
-        let remote_asset = latest.unwrap();
+        };
 
         debug!("Latest remote release: {} ({}).", remote_asset.FileName, remote_version.to_string());
 
+        if let Some(floor) = self.get_version_floor()? {
+            if remote_version < floor {
+                error!("Latest remote release ({}) is lower than the accepted minimum version ({}).", remote_version, floor);
+                return Err(VelopackError::DowngradeBlocked { floor: floor.to_string(), offered: remote_version.to_string() }.into());
+            }
+        }
+
         let mut result: Option<UpdateInfo> = None;
 
         if remote_version > app.version {
@@ -260,7 +1070,10 @@ impl<T: UpdateSource> UpdateManager<T> {
     /// Downloads the specified updates to the local app packages directory. If the update contains delta packages and the delta feature is enabled
     /// this method will attempt to unpack and prepare them. If there is no delta update available, or there is an error preparing delta
     /// packages, this method will fall back to downloading the full version of the update. This function will acquire a global update lock
-    /// so may fail if there is already another update operation in progress.
+    /// so may fail if there is already another update operation in progress. If the server does not declare the size of the
+    /// package, the progress callback receives `sources::INDETERMINATE_PROGRESS` until the download completes. If a download
+    /// condition is not met (see `with_download_condition`), this returns a `VelopackError::DeferredByPolicy` error. Once
+    /// downloaded, the package is checked against the size and SHA1 checksum in the feed, and removed if it does not match.
     pub fn download_updates<A>(&self, update: &UpdateInfo, progress: A) -> Result<()>
     where
         A: FnMut(i16),
@@ -271,20 +1084,29 @@ impl<T: UpdateSource> UpdateManager<T> {
         let target_file = packages_dir.join(name);
 
         if target_file.exists() {
-            info!("Package already exists on disk, skipping download: '{}'", target_file.to_string_lossy());
-            return Ok(());
+            let asset = &update.TargetFullRelease;
+            if asset.SHA1.is_empty() && asset.Size == 0 {
+                warn!(
+                    "Package already exists on disk, but can not be verified as the feed declares no checksum or size, skipping download: '{}'",
+                    target_file.to_string_lossy()
+                );
+                return Ok(());
+            }
+            if asset.matches_local_file(&target_file) {
+                info!("Package already exists on disk, skipping download: '{}'", target_file.to_string_lossy());
+                return Ok(());
+            }
         }
+        self.check_download_conditions()?;
 
         let g = format!("{}/*.nupkg", packages_dir.to_string_lossy());
         info!("Searching for packages to clean in: '{}'", g);
         let mut to_delete = Vec::new();
         match glob::glob(&g) {
             Ok(paths) => {
-                for path in paths {
-                    if let Ok(path) = path {
-                        to_delete.push(path.clone());
-                        debug!("Will delete: '{}'", path.to_string_lossy());
-                    }
+                for path in paths.flatten().filter(|p| *p != target_file) {
+                    to_delete.push(path.clone());
+                    debug!("Will delete: '{}'", path.to_string_lossy());
                 }
             }
             Err(e) => {
@@ -292,9 +1114,16 @@ impl<T: UpdateSource> UpdateManager<T> {
             }
         }
 
+        self.report_phase(UpdatePhase::Downloading);
         self.source.download_release_entry(&update.TargetFullRelease, &target_file.to_string_lossy(), progress)?;
         info!("Successfully placed file: '{}'", target_file.to_string_lossy());
 
+        self.report_phase(UpdatePhase::Verifying);
+        if let Err(e) = self.verify_local(&update.TargetFullRelease, &target_file) {
+            let _ = util::remove_file(&target_file, self.secure_delete);
+            return Err(e);
+        }
+
         // extract new Update.exe on Windows only
         #[cfg(target_os = "windows")]
         match crate::bundle::load_bundle_from_file(&target_file) {
@@ -311,12 +1140,116 @@ impl<T: UpdateSource> UpdateManager<T> {
 
         for path in to_delete {
             info!("Cleaning up old package: '{}'", path.to_string_lossy());
-            let _ = fs::remove_file(&path);
+            let _ = util::remove_file(&path, self.secure_delete);
         }
 
         Ok(())
     }
 
+    /// Downloads the specified update like `download_updates`, but if its package is missing from the update source (eg. a
+    /// 404 because it was deleted from the host), falls back to the next best full release in the feed which is still an
+    /// acceptable update (an older version, or another package for the same version) and tries again. Returns the update
+    /// which was actually downloaded, which should be the one applied. If every candidate is missing, the returned error
+    /// lists each of them; any other kind of failure is returned immediately.
+    pub fn download_updates_with_fallback<A>(&self, update: &UpdateInfo, mut progress: A) -> Result<UpdateInfo>
+    where
+        A: FnMut(i16),
+    {
+        let first_error = match self.download_updates(update, &mut progress) {
+            Ok(()) => return Ok(update.clone()),
+            Err(e) if crate::util::is_not_found_error(&e) => e,
+            Err(e) => return Err(e),
+        };
+        warn!("Package '{}' was not found, looking for another release to download instead.", update.TargetFullRelease.FileName);
+        let mut failures = vec![format!("{} ({})", update.TargetFullRelease.FileName, first_error)];
+
+        let installed = &self.paths.manifest.version;
+        let floor = self.get_version_floor()?;
+        let candidates = self
+            .rank_full_releases(self.get_release_feed()?.Assets)
+            .into_iter()
+            .filter(|(_, asset)| !asset.FileName.eq_ignore_ascii_case(&update.TargetFullRelease.FileName))
+            .filter(|(version, _)| floor.as_ref().is_none_or(|floor| version >= floor))
+            .filter(|(version, _)| version > installed || self.allow_version_downgrade);
+        for (version, asset) in candidates {
+            let candidate = UpdateInfo { TargetFullRelease: asset, IsDowngrade: version <= *installed };
+            info!("Trying to download release {} ({}) instead.", candidate.TargetFullRelease.FileName, version);
+            match self.download_updates(&candidate, &mut progress) {
+                Ok(()) => return Ok(candidate),
+                Err(e) if crate::util::is_not_found_error(&e) => failures.push(format!("{} ({})", candidate.TargetFullRelease.FileName, e)),
+                Err(e) => return Err(e),
+            }
+        }
+        bail!("None of the candidate packages could be found: {}", failures.join("; "));
+    }
+
+    /// Verifies that a package which was downloaded by other means (eg. by a device management tool) matches the size and
+    /// SHA1 checksum declared for the asset by the release feed, without downloading anything. Returns
+    /// `VelopackError::SizeMismatch` or `VelopackError::ChecksumMismatch` if the file does not match.
+    pub fn verify_local<P: AsRef<Path>>(&self, asset: &VelopackAsset, local_file: P) -> Result<()> {
+        let local_file = local_file.as_ref();
+        asset.verify_downloaded_size(local_file)?;
+        asset.verify_checksum(local_file)?;
+        info!("Verified local file '{}' matches the release '{}'.", local_file.to_string_lossy(), asset.FileName);
+        Ok(())
+    }
+
+    /// Downloads the delta packages needed to reach the target release and passes them, oldest first, to the provided
+    /// callback which should apply them to the installed app. If there is no complete delta chain in the feed, a delta
+    /// package can not be downloaded or does not match the feed, or the callback returns an error (eg. because the local
+    /// install has been modified), the full package is downloaded instead with `download_updates`, and its path is
+    /// returned so it can be applied normally.
+    pub fn download_updates_prefer_delta<F, A>(&self, update: &UpdateInfo, apply_deltas: F, mut progress: A) -> Result<DeltaUpdateOutcome>
+    where
+        F: FnOnce(&[PathBuf]) -> Result<()>,
+        A: FnMut(i16),
+    {
+        self.check_download_conditions()?;
+        let target_version = Version::parse(&update.TargetFullRelease.Version)?;
+        let feed = self.get_release_feed()?;
+        if let Some(plan) = feed.get_delta_plan(&self.paths.manifest.version, &target_version) {
+            match self.download_delta_chain(&plan.DeltasToTarget, &mut progress).and_then(|files| apply_deltas(&files)) {
+                Ok(()) => {
+                    info!("Applied {} delta package(s) to reach version {}.", plan.DeltasToTarget.len(), target_version);
+                    return Ok(DeltaUpdateOutcome::DeltasApplied);
+                }
+                Err(e) => warn!("Failed to download, verify or apply delta packages, falling back to the full package: {}", e),
+            }
+        } else {
+            info!("No complete delta chain to version {} was found, downloading the full package.", target_version);
+        }
+
+        self.download_updates(update, progress)?;
+        Ok(DeltaUpdateOutcome::FullPackage(self.paths.packages_dir.join(&update.TargetFullRelease.FileName)))
+    }
+
+    /// Downloads each delta package in a chain to the packages directory and verifies it against the feed, returning
+    /// their paths in the same order. The progress reported covers the whole chain.
+    fn download_delta_chain<A: FnMut(i16)>(&self, deltas: &[VelopackAsset], progress: &mut A) -> Result<Vec<PathBuf>> {
+        fs::create_dir_all(&self.paths.packages_dir)?;
+        self.report_phase(UpdatePhase::Downloading);
+        let count = deltas.len() as i64;
+        let mut delta_files = Vec::new();
+        for (i, delta) in deltas.iter().enumerate() {
+            let delta_file = self.paths.packages_dir.join(crate::sources::checked_file_name(&delta.FileName)?);
+            self.source.ensure_downloaded(delta, &delta_file.to_string_lossy(), |p| {
+                if p == crate::sources::INDETERMINATE_PROGRESS {
+                    progress(p);
+                } else {
+                    // a long chain would overflow i16 before dividing
+                    progress(((i as i64 * 100 + p as i64) / count).clamp(0, 100) as i16);
+                }
+            })?;
+            delta_files.push(delta_file);
+        }
+
+        self.report_phase(UpdatePhase::Verifying);
+        for (delta, file) in deltas.iter().zip(&delta_files) {
+            self.verify_local(delta, file)?;
+        }
+        Ok(delta_files)
+    }
+
     #[cfg(feature = "async")]
     /// Downloads the specified updates to the local app packages directory. If the update contains delta packages and the delta feature is enabled
     /// this method will attempt to unpack and prepare them. If there is no delta update available, or there is an error preparing delta
@@ -415,7 +1348,7 @@ impl<T: UpdateSource> UpdateManager<T> {
     }
 }
 
-fn get_default_channel() -> String {
+pub(crate) fn get_default_channel() -> String {
     #[cfg(target_os = "windows")]
     return "win".to_owned();
     #[cfg(target_os = "linux")]
@@ -423,3 +1356,874 @@ fn get_default_channel() -> String {
     #[cfg(target_os = "macos")]
     return "osx".to_owned();
 }
+
+#[cfg(test)]
+fn test_asset(version: &str, file_name: &str, sha1: &str) -> VelopackAsset {
+    VelopackAsset {
+        PackageId: "MyApp".to_owned(),
+        Version: version.to_owned(),
+        Type: "Full".to_owned(),
+        FileName: file_name.to_owned(),
+        SHA1: sha1.to_owned(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_merge_feeds_removes_duplicates_and_keeps_union() {
+    let mirror_a = VelopackAssetFeed {
+        Assets: vec![test_asset("1.0.0", "MyApp-1.0.0-full.nupkg", "aaa"), test_asset("1.1.0", "MyApp-1.1.0-full.nupkg", "bbb")],
+        ..Default::default()
+    };
+    let mut mirror_b = VelopackAssetFeed {
+        Assets: vec![test_asset("1.1.0", "myapp-1.1.0-full.nupkg", "BBB"), test_asset("1.2.0", "MyApp-1.2.0-full.nupkg", "ccc")],
+        ..Default::default()
+    };
+    mirror_b.Assets[0].NotesMarkdown = "from mirror b".to_owned();
+
+    let merged = VelopackAssetFeed::merge(vec![mirror_a, mirror_b]);
+    let names: Vec<&str> = merged.Assets.iter().map(|x| x.FileName.as_str()).collect();
+    assert_eq!(names, vec!["MyApp-1.0.0-full.nupkg", "MyApp-1.1.0-full.nupkg", "MyApp-1.2.0-full.nupkg"]);
+    assert_eq!(merged.find("MyApp-1.1.0-full.nupkg").unwrap().NotesMarkdown, "");
+}
+
+#[test]
+fn test_merge_feeds_combines_mirror_urls_and_shards() {
+    let shard = |name: &str| VelopackFeedShard { FileName: name.to_owned(), MaxVersion: "1.0.0".to_owned() };
+    let mut mirror_a = VelopackAssetFeed {
+        Assets: vec![test_asset("1.1.0", "MyApp-1.1.0-full.nupkg", "bbb")],
+        Shards: vec![shard("releases.stable.1.json")],
+        ..Default::default()
+    };
+    mirror_a.Assets[0].Urls = vec!["https://a.example.com/MyApp-1.1.0-full.nupkg".to_owned()];
+    let mut mirror_b = VelopackAssetFeed {
+        Assets: vec![test_asset("1.1.0", "MyApp-1.1.0-full.nupkg", "bbb")],
+        Shards: vec![shard("releases.stable.1.json"), shard("releases.stable.2.json")],
+        ..Default::default()
+    };
+    mirror_b.Assets[0].Urls =
+        vec!["https://b.example.com/MyApp-1.1.0-full.nupkg".to_owned(), "https://a.example.com/MyApp-1.1.0-full.nupkg".to_owned()];
+
+    let merged = VelopackAssetFeed::merge(vec![mirror_a, mirror_b]);
+    assert_eq!(merged.Assets.len(), 1);
+    assert_eq!(merged.Assets[0].Urls, vec!["https://a.example.com/MyApp-1.1.0-full.nupkg", "https://b.example.com/MyApp-1.1.0-full.nupkg"]);
+    let shards: Vec<&str> = merged.Shards.iter().map(|s| s.FileName.as_str()).collect();
+    assert_eq!(shards, vec!["releases.stable.1.json", "releases.stable.2.json"]);
+}
+
+#[test]
+fn test_merge_feeds_keeps_assets_with_different_hashes() {
+    let mirror_a = VelopackAssetFeed { Assets: vec![test_asset("1.0.0", "MyApp-1.0.0-full.nupkg", "aaa")], ..Default::default() };
+    let mirror_b = VelopackAssetFeed { Assets: vec![test_asset("1.0.0", "MyApp-1.0.0-full.nupkg", "ddd")], ..Default::default() };
+    let merged = VelopackAssetFeed::merge(vec![mirror_a, mirror_b]);
+    assert_eq!(merged.Assets.len(), 2);
+}
+
+#[cfg(test)]
+fn test_manager(
+    name: &str,
+    installed: &str,
+    assets: Vec<VelopackAsset>,
+    options: Option<UpdateOptions>,
+) -> (UpdateManager<crate::sources::FileSource>, std::path::PathBuf) {
+    let dir = crate::testing::temp_dir(name);
+    let repo = dir.join("repo");
+    fs::create_dir_all(&repo).unwrap();
+    for asset in &assets {
+        fs::write(repo.join(&asset.FileName), "test").unwrap();
+    }
+    let feed = VelopackAssetFeed { Assets: assets, ..Default::default() };
+    fs::write(repo.join("releases.stable.json"), serde_json::to_string(&feed).unwrap()).unwrap();
+    let locator = crate::testing::test_locator(&dir, installed, "stable");
+    (UpdateManager::new_with_locator(crate::sources::FileSource::new(&repo), options, locator), dir)
+}
+
+#[test]
+fn test_update_phases_are_reported_in_order() {
+    use std::sync::Mutex;
+
+    let mut asset = test_asset("2.0.0", "MyApp-2.0.0-full.nupkg", "");
+    asset.Size = 4;
+    let (um, dir) = test_manager("update-phases", "1.0.0", vec![asset], None);
+
+    let phases = Arc::new(Mutex::new(Vec::new()));
+    let phases_clone = phases.clone();
+    let um = um.on_phase_changed(move |p| phases_clone.lock().unwrap().push(p));
+
+    let update = um.check_for_updates().unwrap().unwrap();
+    um.download_updates(&update, |_| {}).unwrap();
+
+    assert_eq!(
+        *phases.lock().unwrap(),
+        vec![UpdatePhase::FetchingFeed, UpdatePhase::SelectingRelease, UpdatePhase::Downloading, UpdatePhase::Verifying]
+    );
+    assert!(dir.join("packages").join("MyApp-2.0.0-full.nupkg").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_download_updates_rejects_package_which_fails_verification() {
+    let asset = test_asset("2.0.0", "MyApp-2.0.0-full.nupkg", "0000000000000000000000000000000000000000");
+    let (um, dir) = test_manager("update-verify-fails", "1.0.0", vec![asset], None);
+    let update = um.check_for_updates().unwrap().unwrap();
+
+    let err = um.download_updates(&update, |_| {}).unwrap_err();
+    assert!(matches!(err.downcast_ref::<VelopackError>(), Some(VelopackError::ChecksumMismatch { .. })));
+    assert!(!dir.join("packages").join("MyApp-2.0.0-full.nupkg").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_check_for_updates_blocks_downgrade_by_default() {
+    let assets = vec![test_asset("1.0.0", "MyApp-1.0.0-full.nupkg", ""), test_asset("1.5.0", "MyApp-1.5.0-full.nupkg", "")];
+    let (um, dir) = test_manager("downgrade-blocked", "2.0.0", assets, None);
+
+    let err = um.check_for_updates().unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<VelopackError>(),
+        Some(&VelopackError::DowngradeBlocked { floor: "2.0.0".to_owned(), offered: "1.5.0".to_owned() })
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_check_for_updates_allows_explicit_downgrade() {
+    let options = UpdateOptions { AllowVersionDowngrade: true, ..Default::default() };
+    let (um, dir) = test_manager("downgrade-allowed", "2.0.0", vec![test_asset("1.5.0", "MyApp-1.5.0-full.nupkg", "")], Some(options));
+
+    let update = um.check_for_updates().unwrap().unwrap();
+    assert!(update.IsDowngrade);
+    assert_eq!(update.TargetFullRelease.Version, "1.5.0");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_check_for_updates_respects_configured_floor() {
+    let options = UpdateOptions { AllowVersionDowngrade: true, MinimumAcceptedVersion: Some("1.8.0".to_owned()), ..Default::default() };
+    let (um, dir) = test_manager("downgrade-floor", "2.0.0", vec![test_asset("1.5.0", "MyApp-1.5.0-full.nupkg", "")], Some(options));
+
+    let err = um.check_for_updates().unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<VelopackError>(),
+        Some(&VelopackError::DowngradeBlocked { floor: "1.8.0".to_owned(), offered: "1.5.0".to_owned() })
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_check_for_updates_rejects_feed_for_another_app() {
+    let options = UpdateOptions { VerifyFeedAppId: true, ..Default::default() };
+    let mut other_app = test_asset("2.0.0", "OtherApp-2.0.0-full.nupkg", "");
+    other_app.PackageId = "OtherApp".to_owned();
+    let (um, dir) = test_manager("app-id-mismatch", "1.0.0", vec![other_app.clone()], Some(options.clone()));
+
+    let err = um.check_for_updates().unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<VelopackError>(),
+        Some(&VelopackError::AppIdMismatch { expected: "MyApp".to_owned(), found: "OtherApp".to_owned() })
+    );
+    fs::remove_dir_all(dir).unwrap();
+
+    let (um, dir) = test_manager("app-id-match", "1.0.0", vec![test_asset("2.0.0", "MyApp-2.0.0-full.nupkg", "")], Some(options));
+    assert!(um.check_for_updates().unwrap().is_some());
+    fs::remove_dir_all(dir).unwrap();
+
+    let (um, dir) = test_manager("app-id-unchecked", "1.0.0", vec![other_app], None);
+    assert!(um.check_for_updates().unwrap().is_some());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_asset_kind_is_parsed_from_type_or_file_name() {
+    let kind = |asset_type: &str, file_name: &str| {
+        VelopackAsset { Type: asset_type.to_owned(), FileName: file_name.to_owned(), ..Default::default() }.kind()
+    };
+    assert_eq!(kind("Full", "MyApp-1.0.0-full.nupkg"), AssetKind::Full);
+    assert_eq!(kind("delta", "MyApp-1.0.0-delta.nupkg"), AssetKind::Delta);
+    assert_eq!(kind("Portable", "MyApp-win-Portable.zip"), AssetKind::Portable);
+    assert_eq!(kind("Installer", "MyApp-win-Setup.exe"), AssetKind::Setup);
+    // the declared type takes precedence over the file name
+    assert_eq!(kind("Delta", "MyApp-1.0.0-full.nupkg"), AssetKind::Delta);
+
+    assert_eq!(kind("", "MyApp-1.0.0-osx-arm64-full.nupkg"), AssetKind::Full);
+    assert_eq!(kind("", "MyApp-1.0.0-DELTA.nupkg"), AssetKind::Delta);
+    assert_eq!(kind("", "MyApp-win-Portable.zip"), AssetKind::Portable);
+    assert_eq!(kind("", "MyApp.AppImage"), AssetKind::Portable);
+    assert_eq!(kind("", "MyApp-osx-Setup.pkg"), AssetKind::Setup);
+    assert_eq!(kind(" ", "MyApp-win-Setup.msi"), AssetKind::Setup);
+
+    assert_eq!(kind("Symbols", "MyApp-1.0.0-symbols.zip"), AssetKind::Other("Symbols".to_owned()));
+    assert_eq!(kind("", "README.md"), AssetKind::Other(String::new()));
+}
+
+#[test]
+fn test_download_plan_chooses_smaller_option() {
+    let mut assets = Vec::new();
+    for v in ["1.0.0", "1.1.0", "1.2.0"] {
+        let mut full = test_asset(v, &format!("MyApp-{}-full.nupkg", v), "");
+        full.Size = 100;
+        assets.push(full);
+        if v != "1.0.0" {
+            let mut delta = test_asset(v, &format!("MyApp-{}-delta.nupkg", v), "");
+            delta.Type = "Delta".to_owned();
+            delta.Size = 20;
+            assets.push(delta);
+        }
+    }
+    let mut feed = VelopackAssetFeed { Assets: assets, ..Default::default() };
+    let v = |s: &str| Version::parse(s).unwrap();
+    let plan = |feed: &VelopackAssetFeed, preference| feed.get_download_plan(&v("1.0.0"), &v("1.2.0"), preference);
+
+    // deltas are cheaper
+    let chosen = plan(&feed, DownloadPreference::Smallest).unwrap();
+    assert!(matches!(&chosen, DownloadPlan::Deltas(d) if d.DeltasToTarget.len() == 2));
+    assert_eq!(chosen.download_size(), 40);
+    assert!(matches!(plan(&feed, DownloadPreference::ForceFull), Some(DownloadPlan::Full(f)) if f.FileName == "MyApp-1.2.0-full.nupkg"));
+
+    // full is cheaper
+    feed.Assets.iter_mut().filter(|a| a.Type == "Delta").for_each(|a| a.Size = 60);
+    let chosen = plan(&feed, DownloadPreference::Smallest).unwrap();
+    assert!(matches!(&chosen, DownloadPlan::Full(f) if f.FileName == "MyApp-1.2.0-full.nupkg"));
+    assert_eq!(chosen.download_size(), 100);
+    assert_eq!(plan(&feed, DownloadPreference::ForceDelta).unwrap().download_size(), 120);
+
+    // deltas are incomplete
+    feed.Assets.retain(|a| a.FileName != "MyApp-1.1.0-delta.nupkg");
+    assert!(matches!(plan(&feed, DownloadPreference::Smallest), Some(DownloadPlan::Full(_))));
+    assert!(plan(&feed, DownloadPreference::ForceDelta).is_none());
+}
+
+#[test]
+fn test_delta_plan_orders_deltas_between_versions() {
+    let mut assets = Vec::new();
+    for v in ["1.0.0", "1.1.0", "1.2.0", "2.0.0"] {
+        assets.push(test_asset(v, &format!("MyApp-{}-full.nupkg", v), ""));
+        if v != "1.0.0" {
+            let mut delta = test_asset(v, &format!("MyApp-{}-delta.nupkg", v), "");
+            delta.Type = "Delta".to_owned();
+            assets.push(delta);
+        }
+    }
+    assets.reverse();
+    let feed = VelopackAssetFeed { Assets: assets, ..Default::default() };
+    let v = |s: &str| Version::parse(s).unwrap();
+    let names = |plan: &DeltaPlan| plan.DeltasToTarget.iter().map(|d| d.FileName.clone()).collect::<Vec<_>>();
+
+    let plan = feed.get_delta_plan(&v("1.0.0"), &v("2.0.0")).unwrap();
+    assert_eq!(plan.BaseRelease.as_ref().unwrap().FileName, "MyApp-1.0.0-full.nupkg");
+    assert_eq!(names(&plan), vec!["MyApp-1.1.0-delta.nupkg", "MyApp-1.2.0-delta.nupkg", "MyApp-2.0.0-delta.nupkg"]);
+
+    let plan = feed.get_delta_plan(&v("1.1.0"), &v("1.2.0")).unwrap();
+    assert_eq!(plan.BaseRelease.as_ref().unwrap().FileName, "MyApp-1.1.0-full.nupkg");
+    assert_eq!(names(&plan), vec!["MyApp-1.2.0-delta.nupkg"]);
+
+    let plan = feed.get_delta_plan(&v("1.0.5"), &v("1.2.0")).unwrap();
+    assert!(plan.BaseRelease.is_none());
+    assert_eq!(names(&plan), vec!["MyApp-1.1.0-delta.nupkg", "MyApp-1.2.0-delta.nupkg"]);
+
+    assert!(feed.get_delta_plan(&v("2.0.0"), &v("1.0.0")).is_none());
+    assert!(feed.get_delta_plan(&v("1.0.0"), &v("3.0.0")).is_none());
+
+    let mut broken = feed.clone();
+    broken.Assets.retain(|a| a.FileName != "MyApp-1.2.0-delta.nupkg");
+    assert!(broken.get_delta_plan(&v("1.0.0"), &v("2.0.0")).is_none());
+}
+
+#[test]
+fn test_update_summary_counts_intervening_releases() {
+    let asset = |version: &str, kind: &str, size: u64| {
+        let mut a = test_asset(version, &format!("MyApp-{}-{}.nupkg", version, kind.to_lowercase()), "");
+        a.Type = kind.to_owned();
+        a.Size = size;
+        a
+    };
+    let v = |s: &str| Version::parse(s).unwrap();
+    let mut feed = VelopackAssetFeed {
+        Assets: vec![
+            asset("1.0.0", "Full", 100),
+            asset("1.1.0", "Full", 110),
+            asset("1.1.0", "Delta", 10),
+            asset("1.2.0", "Full", 120),
+            asset("1.2.0", "Delta", 20),
+            asset("2.0.0", "Full", 200),
+            asset("2.0.0", "Delta", 30),
+        ],
+        ..Default::default()
+    };
+
+    let summary = feed.get_update_summary(&v("1.0.0")).unwrap();
+    assert_eq!(
+        summary,
+        UpdateSummary {
+            LatestVersion: "2.0.0".to_owned(),
+            ReleasesBehind: 3,
+            DownloadSize: 60,
+            SkippedVersions: vec!["1.1.0".to_owned(), "1.2.0".to_owned()],
+        }
+    );
+
+    let summary = feed.get_update_summary(&v("1.2.0")).unwrap();
+    assert_eq!((summary.ReleasesBehind, summary.DownloadSize, summary.SkippedVersions.len()), (1, 30, 0));
+    assert!(feed.get_update_summary(&v("2.0.0")).is_none());
+
+    feed.Assets.retain(|a| a.FileName != "MyApp-1.2.0-delta.nupkg");
+    let summary = feed.get_update_summary(&v("1.0.0")).unwrap();
+    assert_eq!((summary.ReleasesBehind, summary.DownloadSize), (3, 200));
+}
+
+#[test]
+fn test_prune_packages_keeps_latest_and_installed_versions() {
+    let feed = VelopackAssetFeed {
+        Assets: vec![
+            test_asset("1.0.0", "MyApp-1.0.0-full.nupkg", ""),
+            test_asset("1.1.0", "MyApp-1.1.0-full.nupkg", ""),
+            test_asset("1.1.0", "MyApp-1.1.0-delta.nupkg", ""),
+            test_asset("1.2.0", "MyApp-1.2.0-full.nupkg", ""),
+            test_asset("1.2.0", "MyApp-1.2.0-delta.nupkg", ""),
+            test_asset("2.0.0", "MyApp-2.0.0-full.nupkg", ""),
+        ],
+        ..Default::default()
+    };
+    let dir = crate::testing::temp_dir("prune-packages");
+    let files = [
+        "MyApp-0.8.0-full.nupkg",
+        "MyApp-0.9.0-full.nupkg",
+        "MyApp-1.0.0-full.nupkg",
+        "MyApp-1.1.0-full.nupkg",
+        "MyApp-1.1.0-delta.nupkg",
+        "myapp-1.2.0-FULL.nupkg",
+        "MyApp-1.2.0-delta.nupkg",
+        "MyApp-2.0.0-full.nupkg",
+        "notes.txt",
+    ];
+    for file in files {
+        fs::write(dir.join(file), "test").unwrap();
+    }
+    fs::create_dir(dir.join("nested.nupkg")).unwrap();
+
+    // 0.9.0 is installed, and is no longer in the feed
+    let deleted = feed.prune_packages(&dir, 2, &Version::parse("0.9.0").unwrap(), false).unwrap();
+    let names: Vec<String> = deleted.iter().map(|p| p.file_name().unwrap().to_string_lossy().to_string()).collect();
+    assert_eq!(names, vec!["MyApp-0.8.0-full.nupkg", "MyApp-1.0.0-full.nupkg", "MyApp-1.1.0-delta.nupkg", "MyApp-1.1.0-full.nupkg"]);
+    for kept in ["MyApp-0.9.0-full.nupkg", "myapp-1.2.0-FULL.nupkg", "MyApp-1.2.0-delta.nupkg", "MyApp-2.0.0-full.nupkg", "notes.txt"] {
+        assert!(dir.join(kept).exists(), "{} should be kept", kept);
+    }
+    assert!(dir.join("nested.nupkg").is_dir());
+
+    // the installed version is kept even when it is older than the latest releases, and a secure prune overwrites packages
+    fs::write(dir.join("MyApp-1.1.0-full.nupkg"), "test").unwrap();
+    fs::hard_link(dir.join("MyApp-1.2.0-delta.nupkg"), dir.join("pruned-link")).unwrap();
+    let deleted = feed.prune_packages(&dir, 1, &Version::parse("1.1.0").unwrap(), true).unwrap();
+    let names: Vec<String> = deleted.iter().map(|p| p.file_name().unwrap().to_string_lossy().to_string()).collect();
+    assert_eq!(names, vec!["MyApp-0.9.0-full.nupkg", "MyApp-1.2.0-delta.nupkg", "myapp-1.2.0-FULL.nupkg"]);
+    assert_eq!(fs::read(dir.join("pruned-link")).unwrap(), vec![0u8; 4]);
+    assert!(dir.join("MyApp-1.1.0-full.nupkg").exists());
+    assert!(dir.join("MyApp-2.0.0-full.nupkg").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_legacy_releases_file_is_parsed() {
+    let releases = "\u{feff}E3F67244E4166A65310C816221A12685C83F8E6F MyApp-1.0.0-full.nupkg 600725  \r\n\
+        \r\n\
+        0D777EA94C612E8BF1EA7379164CAEFBA5A6D3F8 My-App-1.1.0-beta.1-delta.nupkg 12345\n\
+        # 50% 85F4D657F8424DD437D1B33CC4511EA7AD86B1A7 https://cdn.example.com/My-App-1.1.0-beta.1-full.nupkg 600920\n\
+        85F4D657F8424DD437D1B33CC4511EA7AD86B1A7\thttps://cdn.example.com/My-App-1.1.0-beta.1-full.nupkg\t600920\n\n";
+    let feed = VelopackAssetFeed::from_legacy_releases(releases).unwrap();
+    let summary: Vec<(&str, &str, &str, &str, u64)> =
+        feed.Assets.iter().map(|a| (a.PackageId.as_str(), a.Version.as_str(), a.Type.as_str(), a.FileName.as_str(), a.Size)).collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("MyApp", "1.0.0", "Full", "MyApp-1.0.0-full.nupkg", 600725),
+            ("My-App", "1.1.0-beta.1", "Delta", "My-App-1.1.0-beta.1-delta.nupkg", 12345),
+            ("My-App", "1.1.0-beta.1", "Full", "My-App-1.1.0-beta.1-full.nupkg", 600920),
+        ]
+    );
+    assert_eq!(feed.Assets[0].SHA1, "E3F67244E4166A65310C816221A12685C83F8E6F");
+    assert!(feed.Assets[0].Urls.is_empty());
+    assert_eq!(feed.Assets[2].Urls, vec!["https://cdn.example.com/My-App-1.1.0-beta.1-full.nupkg"]);
+
+    assert!(VelopackAssetFeed::from_legacy_releases("ABCD MyApp-1.0.0-full.nupkg").is_err());
+    assert!(VelopackAssetFeed::from_legacy_releases("ABCD MyApp-1.0.0-full.nupkg large").is_err());
+    assert!(VelopackAssetFeed::from_legacy_releases("ABCD MyApp-full.nupkg 10").is_err());
+    assert!(VelopackAssetFeed::from_legacy_releases(" \n\n").unwrap().Assets.is_empty());
+}
+
+#[test]
+fn test_get_assets_for_version_returns_every_asset_type() {
+    let asset = |version: &str, kind: &str, file_name: &str| {
+        let mut a = test_asset(version, file_name, "");
+        a.Type = kind.to_owned();
+        a
+    };
+    let feed = VelopackAssetFeed {
+        Assets: vec![
+            asset("1.0.0", "Full", "MyApp-1.0.0-full.nupkg"),
+            asset("1.1.0", "Full", "MyApp-1.1.0-full.nupkg"),
+            asset("1.1.0", "Delta", "MyApp-1.1.0-delta.nupkg"),
+            asset("1.2.0", "Full", "MyApp-1.2.0-full.nupkg"),
+            asset("1.1.0", "Portable", "MyApp-win-Portable.zip"),
+            asset("1.1.0", "Installer", "MyApp-win-Setup.exe"),
+        ],
+        ..Default::default()
+    };
+    let names = |version: &str| feed.get_assets_for_version(version).into_iter().map(|a| a.FileName).collect::<Vec<_>>();
+    let expected = vec!["MyApp-1.1.0-full.nupkg", "MyApp-1.1.0-delta.nupkg", "MyApp-win-Portable.zip", "MyApp-win-Setup.exe"];
+    assert_eq!(names("1.1.0"), expected);
+    assert_eq!(names("v1.1.0"), expected);
+    assert_eq!(names("1.0.0"), vec!["MyApp-1.0.0-full.nupkg"]);
+    assert!(names("1.1.0-beta").is_empty());
+    assert!(names("3.0.0").is_empty());
+    assert!(names("not-a-version").is_empty());
+}
+
+#[test]
+fn test_update_is_mandatory_if_intervening_release_is_mandatory() {
+    let json = r#"{"Assets":[
+        {"Version":"1.0.0","Type":"Full","FileName":"MyApp-1.0.0-full.nupkg","Critical":true},
+        {"Version":"1.1.0","Type":"Full","FileName":"MyApp-1.1.0-full.nupkg","Mandatory":true},
+        {"Version":"1.2.0","Type":"Full","FileName":"MyApp-1.2.0-full.nupkg"},
+        {"Version":"2.0.0","Type":"Full","FileName":"MyApp-2.0.0-full.nupkg"}]}"#;
+    let feed: VelopackAssetFeed = serde_json::from_str(json).unwrap();
+    let v = |s: &str| Version::parse(s).unwrap();
+    assert!(feed.Assets[0].IsMandatory);
+    assert!(feed.is_update_mandatory(&v("0.9.0")));
+    assert!(feed.is_update_mandatory(&v("1.0.0")));
+    assert!(!feed.is_update_mandatory(&v("1.1.0")));
+    assert!(!feed.is_update_mandatory(&v("2.0.0")));
+
+    let optional = VelopackAssetFeed { Assets: feed.Assets.iter().filter(|a| !a.IsMandatory).cloned().collect(), ..Default::default() };
+    assert!(!optional.is_update_mandatory(&v("1.0.0")));
+    assert!(!serde_json::to_string(&optional).unwrap().contains("IsMandatory"));
+}
+
+#[test]
+fn test_equal_version_assets_are_chosen_deterministically() {
+    let asset = |file_name: &str, size: u64| {
+        let mut a = test_asset("2.0.0", file_name, "");
+        a.Size = size;
+        a
+    };
+    let assets = vec![
+        asset("MyApp-2.0.0-osx-arm64-full.nupkg", 30),
+        asset("MyApp-2.0.0-win-x64-full.nupkg", 50),
+        asset("MyApp-2.0.0-win-x86-full.nupkg", 30),
+    ];
+
+    let choose = |options: Option<UpdateOptions>, reverse: bool| {
+        let mut assets = assets.clone();
+        if reverse {
+            assets.reverse();
+        }
+        let (um, dir) = test_manager(&format!("tie-break-{}", reverse), "1.0.0", assets, options);
+        let chosen = um.check_for_updates().unwrap().unwrap().TargetFullRelease.FileName;
+        fs::remove_dir_all(dir).unwrap();
+        chosen
+    };
+
+    // smallest size, then lowest file name
+    assert_eq!(choose(None, false), "MyApp-2.0.0-osx-arm64-full.nupkg");
+    assert_eq!(choose(None, true), "MyApp-2.0.0-osx-arm64-full.nupkg");
+
+    let policy = AssetSelectionPolicy { PreferredRid: Some("WIN-X64".to_owned()), ..Default::default() };
+    let options = UpdateOptions { AssetSelection: policy, ..Default::default() };
+    assert_eq!(choose(Some(options.clone()), false), "MyApp-2.0.0-win-x64-full.nupkg");
+    assert_eq!(choose(Some(options), true), "MyApp-2.0.0-win-x64-full.nupkg");
+
+    let mut delta = asset("MyApp-2.0.0-delta.nupkg", 1);
+    delta.Type = "Delta".to_owned();
+    let policy = AssetSelectionPolicy::default();
+    assert_eq!(policy.compare(&assets[1], &delta), Ordering::Less);
+    let policy = AssetSelectionPolicy { PreferFull: false, ..Default::default() };
+    assert_eq!(policy.compare(&assets[1], &delta), Ordering::Greater);
+}
+
+#[test]
+fn test_release_comparator_controls_ordering() {
+    let build_number = |a: &VelopackAsset| a.NotesMarkdown.parse::<u32>().unwrap_or(0);
+    let asset = |version: &str, file_name: &str, build: u32| {
+        let mut a = test_asset(version, file_name, "");
+        a.NotesMarkdown = build.to_string();
+        a
+    };
+    let assets = vec![
+        asset("2.0.0", "MyApp-2.0.0-full.nupkg", 14),
+        asset("3.0.0", "MyApp-3.0.0-full.nupkg", 12),
+        asset("2.5.0", "MyApp-2.5.0-osx-arm64-full.nupkg", 99),
+        asset("2.1.0", "MyApp-2.1.0-full.nupkg", 13),
+    ];
+    let options = UpdateOptions { ForceRid: Some("win-x64".to_owned()), ..Default::default() };
+    let (um, dir) = test_manager("release-comparator", "1.0.0", assets.clone(), Some(options));
+    let names = |um: &UpdateManager<crate::sources::FileSource>| {
+        um.rank_full_releases(assets.clone()).into_iter().map(|(_, a)| a.FileName).collect::<Vec<_>>()
+    };
+    assert_eq!(names(&um), vec!["MyApp-3.0.0-full.nupkg", "MyApp-2.1.0-full.nupkg", "MyApp-2.0.0-full.nupkg"]);
+
+    let um = um.with_release_comparator(move |a, b| build_number(a).cmp(&build_number(b)));
+    assert_eq!(names(&um), vec!["MyApp-2.0.0-full.nupkg", "MyApp-2.1.0-full.nupkg", "MyApp-3.0.0-full.nupkg"]);
+    assert_eq!(um.check_for_updates().unwrap().unwrap().TargetFullRelease.Version, "2.0.0");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_asset_selection_does_not_depend_on_feed_order() {
+    let asset = |file_name: &str, sha1: &str, size: u64| {
+        let mut a = test_asset("2.0.0", file_name, sha1);
+        a.Size = size;
+        a
+    };
+    let assets = vec![
+        asset("MyApp-2.0.0-win-x64-full.nupkg", "bbbb", 40),
+        asset("MyApp-2.0.0-win-x64-full.nupkg", "aaaa", 40),
+        asset("MyApp-2.0.0-linux-x64-full.nupkg", "cccc", 40),
+        asset("MyApp-2.0.0-full.nupkg", "dddd", 40),
+        asset("myapp-2.0.0-full.nupkg", "eeee", 40),
+        asset("MyApp-2.0.0-win-x64-full.nupkg", "aaaa", 50),
+    ];
+    let (um, dir) = test_manager("stable-selection", "1.0.0", Vec::new(), None);
+    let policy = AssetSelectionPolicy { PreferSmallest: false, ..Default::default() };
+    let um = UpdateManager { asset_selection: policy, ..um };
+    let order = |assets: Vec<VelopackAsset>| {
+        um.rank_full_releases(assets).into_iter().map(|(_, a)| (a.FileName, a.SHA1, a.Size)).collect::<Vec<_>>()
+    };
+
+    let expected = order(assets.clone());
+    assert_eq!(expected[0], ("MyApp-2.0.0-full.nupkg".to_owned(), "dddd".to_owned(), 40));
+    for i in 0..assets.len() {
+        let mut shuffled = assets.clone();
+        shuffled.rotate_left(i);
+        assert_eq!(order(shuffled.clone()), expected);
+        shuffled.reverse();
+        assert_eq!(order(shuffled.clone()), expected);
+        shuffled.swap(0, assets.len() - 1 - i);
+        assert_eq!(order(shuffled), expected);
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_prefer_delta_falls_back_to_full_package() {
+    let mut assets = vec![test_asset("1.0.0", "MyApp-1.0.0-full.nupkg", ""), test_asset("1.1.0", "MyApp-1.1.0-full.nupkg", "")];
+    let mut delta = test_asset("1.1.0", "MyApp-1.1.0-delta.nupkg", "");
+    delta.Type = "Delta".to_owned();
+    assets.push(delta);
+    let (um, dir) = test_manager("prefer-delta", "1.0.0", assets, None);
+    let update = um.check_for_updates().unwrap().unwrap();
+    let packages_dir = dir.join("packages");
+
+    let mut applied = Vec::new();
+    let outcome = um
+        .download_updates_prefer_delta(
+            &update,
+            |files| {
+                applied = files.to_vec();
+                Ok(())
+            },
+            |_| {},
+        )
+        .unwrap();
+    assert_eq!(outcome, DeltaUpdateOutcome::DeltasApplied);
+    assert_eq!(applied, vec![packages_dir.join("MyApp-1.1.0-delta.nupkg")]);
+    assert!(!packages_dir.join("MyApp-1.1.0-full.nupkg").exists());
+
+    let outcome = um.download_updates_prefer_delta(&update, |_| bail!("local install was modified"), |_| {}).unwrap();
+    let full = packages_dir.join("MyApp-1.1.0-full.nupkg");
+    assert_eq!(outcome, DeltaUpdateOutcome::FullPackage(full.clone()));
+    assert_eq!(fs::read_to_string(full).unwrap(), "test");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_prefer_delta_falls_back_when_deltas_can_not_be_downloaded() {
+    let full = || vec![test_asset("1.0.0", "MyApp-1.0.0-full.nupkg", ""), test_asset("1.1.0", "MyApp-1.1.0-full.nupkg", "")];
+    for (name, delta_name) in
+        [("prefer-delta-missing", "MyApp-1.1.0-delta.nupkg"), ("prefer-delta-traversal", "../MyApp-1.1.0-delta.nupkg")]
+    {
+        let mut assets = full();
+        let mut delta = test_asset("1.1.0", delta_name, "");
+        delta.Type = "Delta".to_owned();
+        assets.push(delta);
+        let (um, dir) = test_manager(name, "1.0.0", assets, None);
+        let _ = fs::remove_file(dir.join("repo").join("MyApp-1.1.0-delta.nupkg"));
+        let update = um.check_for_updates().unwrap().unwrap();
+
+        let outcome = um.download_updates_prefer_delta(&update, |_| panic!("no deltas should be applied"), |_| {}).unwrap();
+        assert_eq!(outcome, DeltaUpdateOutcome::FullPackage(dir.join("packages").join("MyApp-1.1.0-full.nupkg")));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+#[test]
+fn test_verify_local_checks_size_and_checksum() {
+    let (um, dir) = test_manager("verify-local", "1.0.0", vec![], None);
+    let local_file = dir.join("MyApp-1.1.0-full.nupkg");
+    fs::write(&local_file, "test").unwrap();
+
+    let mut asset = test_asset("1.1.0", "MyApp-1.1.0-full.nupkg", "A94A8FE5CCB19BA61C4C0873D391E987982FBBD3");
+    asset.Size = 4;
+    um.verify_local(&asset, &local_file).unwrap();
+
+    asset.Size = 5;
+    let err = um.verify_local(&asset, &local_file).unwrap_err();
+    assert_eq!(err.downcast_ref::<VelopackError>(), Some(&VelopackError::SizeMismatch { expected: 5, actual: 4 }));
+
+    asset.Size = 4;
+    asset.SHA1 = "0000000000000000000000000000000000000000".to_owned();
+    let err = um.verify_local(&asset, &local_file).unwrap_err();
+    let expected =
+        VelopackError::ChecksumMismatch { expected: asset.SHA1.clone(), actual: "a94a8fe5ccb19ba61c4c0873d391e987982fbbd3".to_owned() };
+    assert_eq!(err.downcast_ref::<VelopackError>(), Some(&expected));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_feed_minimum_client_version_is_checked() {
+    let feed: VelopackAssetFeed = serde_json::from_str(r#"{"Assets":[],"MinimumVersion":"1.2.0"}"#).unwrap();
+    feed.check_client_version(&Version::parse("1.2.0").unwrap()).unwrap();
+    feed.check_client_version(&Version::parse("2.0.0").unwrap()).unwrap();
+    let err = feed.check_client_version(&Version::parse("1.1.9").unwrap()).unwrap_err();
+    let expected = VelopackError::ClientTooOld { required: "1.2.0".to_owned(), current: "1.1.9".to_owned() };
+    assert_eq!(err.downcast_ref::<VelopackError>(), Some(&expected));
+
+    let legacy: VelopackAssetFeed = serde_json::from_str(r#"{"Assets":[],"RuntimeMin":"3.0.0"}"#).unwrap();
+    assert_eq!(legacy.MinimumVersion, "3.0.0");
+    let unrestricted: VelopackAssetFeed = serde_json::from_str(r#"{"Assets":[]}"#).unwrap();
+    unrestricted.check_client_version(&Version::parse("0.0.1").unwrap()).unwrap();
+
+    let merged = VelopackAssetFeed::merge(vec![feed, legacy, unrestricted]);
+    assert_eq!(merged.MinimumVersion, "3.0.0");
+}
+
+#[test]
+fn test_check_for_updates_uses_source_default_channel() {
+    let (um, dir) = test_manager("source-default-channel", "1.0.0", Vec::new(), None);
+    let repo = dir.join("repo");
+    let feed = VelopackAssetFeed { Assets: vec![test_asset("3.0.0", "MyApp-3.0.0-full.nupkg", "")], ..Default::default() };
+    fs::write(repo.join("releases.nightly.json"), serde_json::to_string(&feed).unwrap()).unwrap();
+    let source =
+        crate::sources::FileSource::new(&repo).with_feed_options(crate::sources::FeedOptions::new().with_default_channel("nightly"));
+    let um = UpdateManager::new_with_locator(source, None, um.paths.clone());
+    assert_eq!(um.check_for_updates().unwrap().unwrap().TargetFullRelease.Version, "3.0.0");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_feed_freshness_is_checked() {
+    let now = util::parse_rfc3339("2024-06-01T00:00:00Z").unwrap();
+    let day = Duration::from_secs(86400);
+    let fresh: VelopackAssetFeed =
+        serde_json::from_str(r#"{"Assets":[],"Published":"2024-05-31T00:00:00Z","Expires":"2024-06-30T00:00:00Z"}"#).unwrap();
+    fresh.check_freshness(now, None).unwrap();
+    fresh.check_freshness(now, Some(day * 2)).unwrap();
+    let err = fresh.check_freshness(now, Some(day / 2)).unwrap_err();
+    assert!(matches!(err.downcast_ref::<VelopackError>(), Some(VelopackError::FeedStale { .. })));
+
+    let expired: VelopackAssetFeed = serde_json::from_str(r#"{"Assets":[],"ExpiresAt":"2024-05-01T00:00:00Z"}"#).unwrap();
+    let err = expired.check_freshness(now, None).unwrap_err();
+    let expected = VelopackError::FeedStale { published: String::new(), expires: "2024-05-01T00:00:00Z".to_owned() };
+    assert_eq!(err.downcast_ref::<VelopackError>(), Some(&expected));
+
+    let undated: VelopackAssetFeed = serde_json::from_str(r#"{"Assets":[]}"#).unwrap();
+    undated.check_freshness(now, Some(day)).unwrap();
+    let invalid: VelopackAssetFeed = serde_json::from_str(r#"{"Assets":[],"Expires":"next tuesday"}"#).unwrap();
+    assert!(invalid.check_freshness(now, None).unwrap_err().downcast_ref::<VelopackError>().is_none());
+
+    let merged = VelopackAssetFeed::merge(vec![expired, fresh.clone(), undated]);
+    assert_eq!(merged.Published, fresh.Published);
+    assert_eq!(merged.Expires, fresh.Expires);
+}
+
+#[test]
+fn test_check_for_updates_handles_stale_feed() {
+    let assets = vec![test_asset("2.0.0", "MyApp-2.0.0-full.nupkg", "")];
+    let options = UpdateOptions { MaximumFeedAge: Some(Duration::from_secs(3600)), RejectStaleFeeds: true, ..Default::default() };
+    let (um, dir) = test_manager("stale-feed", "1.0.0", assets, Some(options));
+    let feed_path = dir.join("repo").join("releases.stable.json");
+    let mut feed: VelopackAssetFeed = serde_json::from_slice(&fs::read(&feed_path).unwrap()).unwrap();
+    assert!(um.check_for_updates().unwrap().is_some());
+
+    feed.Published = "2001-01-01T00:00:00Z".to_owned();
+    fs::write(&feed_path, serde_json::to_string(&feed).unwrap()).unwrap();
+    let err = um.check_for_updates().unwrap_err();
+    assert!(matches!(err.downcast_ref::<VelopackError>(), Some(VelopackError::FeedStale { .. })));
+
+    let warn_only = UpdateManager::new_with_locator(
+        um.source.clone(),
+        Some(UpdateOptions { MaximumFeedAge: Some(Duration::from_secs(3600)), ..Default::default() }),
+        um.paths.clone(),
+    );
+    assert!(warn_only.check_for_updates().unwrap().is_some());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_check_for_updates_rejects_feed_requiring_newer_client() {
+    let options = UpdateOptions { ClientVersion: Some("1.5.0".to_owned()), ..Default::default() };
+    let assets = vec![test_asset("2.0.0", "MyApp-2.0.0-full.nupkg", "")];
+    let (um, dir) = test_manager("client-too-old", "1.0.0", assets, Some(options));
+    let feed_path = dir.join("repo").join("releases.stable.json");
+    let mut feed: VelopackAssetFeed = serde_json::from_slice(&fs::read(&feed_path).unwrap()).unwrap();
+    assert!(um.check_for_updates().unwrap().is_some());
+
+    feed.MinimumVersion = "1.5.0".to_owned();
+    fs::write(&feed_path, serde_json::to_string(&feed).unwrap()).unwrap();
+    assert!(um.check_for_updates().unwrap().is_some());
+
+    feed.MinimumVersion = "1.6.0".to_owned();
+    fs::write(&feed_path, serde_json::to_string(&feed).unwrap()).unwrap();
+    let err = um.check_for_updates().unwrap_err();
+    let expected = VelopackError::ClientTooOld { required: "1.6.0".to_owned(), current: "1.5.0".to_owned() };
+    assert_eq!(err.downcast_ref::<VelopackError>(), Some(&expected));
+
+    // without a configured version, a local build of this crate does not check the minimum version
+    if env!("CARGO_PKG_VERSION").ends_with("-local") {
+        let unconfigured = UpdateManager::new_with_locator(
+            crate::sources::FileSource::new(dir.join("repo")),
+            None,
+            crate::testing::test_locator(&dir, "1.0.0", "stable"),
+        );
+        assert!(unconfigured.check_for_updates().unwrap().is_some());
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_host_rid_is_detected_for_each_platform() {
+    assert_eq!(host_rid_for("windows", "x86_64", false), "win-x64");
+    assert_eq!(host_rid_for("windows", "aarch64", false), "win-arm64");
+    assert_eq!(host_rid_for("windows", "x86", false), "win-x86");
+    assert_eq!(host_rid_for("macos", "x86_64", false), "osx-x64");
+    assert_eq!(host_rid_for("macos", "x86_64", true), "osx-arm64");
+    assert_eq!(host_rid_for("macos", "aarch64", false), "osx-arm64");
+    assert_eq!(host_rid_for("linux", "x86_64", false), "linux-x64");
+    assert_eq!(host_rid_for("linux", "aarch64", false), "linux-arm64");
+    assert!(KNOWN_RIDS.contains(&get_host_rid().as_str()));
+
+    assert_eq!(get_file_name_rid("MyApp-2.0.0-osx-arm64-full.nupkg"), Some("osx-arm64"));
+    assert_eq!(get_file_name_rid("MyApp-2.0.0-WIN-X64.nupkg"), Some("win-x64"));
+    assert_eq!(get_file_name_rid("MyApp-2.0.0-full.nupkg"), None);
+}
+
+#[test]
+fn test_check_for_updates_selects_asset_for_runtime() {
+    let mut assets = vec![test_asset("2.0.0", "MyApp-2.0.0-full.nupkg", "")];
+    for rid in ["win-x64", "win-arm64", "osx-x64", "osx-arm64", "linux-x64"] {
+        let mut asset = test_asset("2.0.0", &format!("MyApp-2.0.0-{}-full.nupkg", rid), "");
+        asset.Size = 10;
+        assets.push(asset);
+    }
+    // a newer release which was only published for one platform should not be offered to the others
+    assets.push(test_asset("2.1.0", "MyApp-2.1.0-win-x64-full.nupkg", ""));
+
+    let choose = |options: UpdateOptions| {
+        let (um, dir) = test_manager("rid-selection", "1.0.0", assets.clone(), Some(options));
+        let chosen = um.check_for_updates().unwrap().unwrap().TargetFullRelease.FileName;
+        fs::remove_dir_all(dir).unwrap();
+        chosen
+    };
+    let forced = |rid: &str| choose(UpdateOptions { ForceRid: Some(rid.to_owned()), ..Default::default() });
+    assert_eq!(forced("win-x64"), "MyApp-2.1.0-win-x64-full.nupkg");
+    assert_eq!(forced("win-arm64"), "MyApp-2.0.0-win-arm64-full.nupkg");
+    assert_eq!(forced("osx-x64"), "MyApp-2.0.0-osx-x64-full.nupkg");
+    assert_eq!(forced("OSX-ARM64"), "MyApp-2.0.0-osx-arm64-full.nupkg");
+    assert_eq!(forced("linux-x64"), "MyApp-2.0.0-linux-x64-full.nupkg");
+    assert_eq!(forced("linux-arm64"), "MyApp-2.0.0-full.nupkg");
+
+    let host = choose(UpdateOptions { MatchHostRid: true, ..Default::default() });
+    let expected = match get_host_rid().as_str() {
+        "win-x64" => "MyApp-2.1.0-win-x64-full.nupkg".to_owned(),
+        rid if ["win-arm64", "osx-x64", "osx-arm64", "linux-x64"].contains(&rid) => format!("MyApp-2.0.0-{}-full.nupkg", rid),
+        _ => "MyApp-2.0.0-full.nupkg".to_owned(),
+    };
+    assert_eq!(host, expected);
+    assert_eq!(choose(UpdateOptions::default()), "MyApp-2.1.0-win-x64-full.nupkg");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_download_updates_securely_deletes_old_packages() {
+    let assets = vec![test_asset("2.0.0", "MyApp-2.0.0-full.nupkg", "")];
+    let (um, dir) = test_manager("secure-delete", "1.0.0", assets, Some(UpdateOptions { SecureDelete: true, ..Default::default() }));
+    fs::create_dir_all(&um.paths.packages_dir).unwrap();
+    let old_package = um.paths.packages_dir.join("MyApp-1.0.0-full.nupkg");
+    fs::write(&old_package, "old package").unwrap();
+    let link = dir.join("old-package-link");
+    fs::hard_link(&old_package, &link).unwrap();
+
+    let update = um.check_for_updates().unwrap().unwrap();
+    um.download_updates(&update, |_| {}).unwrap();
+    assert!(!old_package.exists());
+    assert_eq!(fs::read(&link).unwrap(), vec![0u8; 11]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_download_updates_keeps_existing_package_which_can_not_be_verified() {
+    let (um, dir) = test_manager("unverifiable-package", "1.0.0", vec![test_asset("2.0.0", "MyApp-2.0.0-full.nupkg", "")], None);
+    fs::create_dir_all(&um.paths.packages_dir).unwrap();
+    let existing = um.paths.packages_dir.join("MyApp-2.0.0-full.nupkg");
+    fs::write(&existing, "existing").unwrap();
+
+    let update = um.check_for_updates().unwrap().unwrap();
+    um.download_updates(&update, |_| {}).unwrap();
+    assert_eq!(fs::read_to_string(&existing).unwrap(), "existing");
+
+    let mut sized = update.clone();
+    sized.TargetFullRelease.Size = 4;
+    um.download_updates(&sized, |_| {}).unwrap();
+    assert_eq!(fs::read_to_string(&existing).unwrap(), "test");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_download_is_deferred_when_condition_is_not_met() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let mut asset = test_asset("2.0.0", "MyApp-2.0.0-full.nupkg", "");
+    asset.Size = 4;
+    let (um, dir) = test_manager("download-conditions", "1.0.0", vec![asset], None);
+    let on_ac_power = Arc::new(AtomicBool::new(false));
+    let on_ac_power_clone = on_ac_power.clone();
+    let um = um
+        .with_download_condition("low memory pressure", || true)
+        .with_download_condition("on AC power", move || on_ac_power_clone.load(Ordering::SeqCst));
+
+    let update = um.check_for_updates().unwrap().unwrap();
+    let err = um.download_updates(&update, |_| {}).unwrap_err();
+    assert_eq!(err.downcast_ref::<VelopackError>(), Some(&VelopackError::DeferredByPolicy { reason: "on AC power".to_owned() }));
+    assert!(!um.paths.packages_dir.join("MyApp-2.0.0-full.nupkg").exists());
+    let err = um.download_updates_with_fallback(&update, |_| {}).unwrap_err();
+    assert!(matches!(err.downcast_ref::<VelopackError>(), Some(VelopackError::DeferredByPolicy { .. })));
+
+    on_ac_power.store(true, Ordering::SeqCst);
+    um.download_updates(&update, |_| {}).unwrap();
+    assert!(um.paths.packages_dir.join("MyApp-2.0.0-full.nupkg").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_download_updates_falls_back_when_package_is_missing() {
+    let assets = vec![
+        test_asset("1.1.0", "MyApp-1.1.0-full.nupkg", ""),
+        test_asset("1.2.0", "MyApp-1.2.0-full.nupkg", ""),
+        test_asset("1.3.0", "MyApp-1.3.0-full.nupkg", ""),
+    ];
+    let (um, dir) = test_manager("download-fallback", "1.0.0", assets, None);
+    fs::remove_file(dir.join("repo").join("MyApp-1.3.0-full.nupkg")).unwrap();
+
+    let update = um.check_for_updates().unwrap().unwrap();
+    assert_eq!(update.TargetFullRelease.Version, "1.3.0");
+    let downloaded = um.download_updates_with_fallback(&update, |_| {}).unwrap();
+    assert_eq!(downloaded.TargetFullRelease.Version, "1.2.0");
+    assert!(!downloaded.IsDowngrade);
+    assert!(um.paths.packages_dir.join("MyApp-1.2.0-full.nupkg").exists());
+
+    // the downloaded package is kept when it can not be verified, so remove it to make every candidate unavailable
+    fs::remove_file(um.paths.packages_dir.join("MyApp-1.2.0-full.nupkg")).unwrap();
+    fs::remove_file(dir.join("repo").join("MyApp-1.2.0-full.nupkg")).unwrap();
+    fs::remove_file(dir.join("repo").join("MyApp-1.1.0-full.nupkg")).unwrap();
+    let err = um.download_updates_with_fallback(&update, |_| {}).unwrap_err().to_string();
+    assert!(err.contains("MyApp-1.3.0-full.nupkg") && err.contains("MyApp-1.2.0-full.nupkg") && err.contains("MyApp-1.1.0-full.nupkg"));
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -20,6 +20,13 @@ pub struct Manifest {
     pub channel: String,
 }
 
+impl Manifest {
+    /// Returns the channel the app was packaged for, or None if the manifest does not declare one.
+    pub fn installed_channel(&self) -> Option<&str> {
+        Some(self.channel.trim()).filter(|c| !c.is_empty())
+    }
+}
+
 pub fn read_manifest_from_string(xml: &str) -> Result<Manifest> {
     let mut obj: Manifest = Default::default();
     let cursor = Cursor::new(xml);
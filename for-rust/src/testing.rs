@@ -0,0 +1,217 @@
+#![allow(dead_code)]
+
+use semver::Version;
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use crate::{locator::VelopackLocator, manifest::Manifest};
+
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("velopack-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub fn test_manifest(version: &str, channel: &str) -> Manifest {
+    Manifest { id: "MyApp".to_owned(), version: Version::parse(version).unwrap(), channel: channel.to_owned(), ..Default::default() }
+}
+
+pub fn test_locator(root: &Path, version: &str, channel: &str) -> VelopackLocator {
+    VelopackLocator {
+        root_app_dir: root.to_path_buf(),
+        update_exe_path: root.join("Update.exe"),
+        packages_dir: root.join("packages"),
+        manifest: test_manifest(version, channel),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl MockRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl MockResponse {
+    pub fn ok<B: Into<Vec<u8>>>(body: B) -> MockResponse {
+        MockResponse { status: 200, headers: Vec::new(), body: body.into() }
+    }
+
+    pub fn status(status: u16) -> MockResponse {
+        MockResponse { status, headers: Vec::new(), body: Vec::new() }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> MockResponse {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+}
+
+type MockHandler = Arc<dyn Fn(&MockRequest) -> MockResponse + Send + Sync>;
+
+pub struct MockServer {
+    pub url: String,
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl MockServer {
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+static CAPTURED_LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct CaptureLogger;
+
+impl log::Log for CaptureLogger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        // only messages from this crate, dependencies like ureq do their own (unsanitized) trace logging
+        if record.target().starts_with("velopack") {
+            CAPTURED_LOGS.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Starts recording the log messages of this crate. Tests run in parallel and share the logger, so a test should filter the
+/// output of `captured_logs` by something unique to it (eg. its mock server url).
+pub fn capture_logs() {
+    if log::set_logger(&CaptureLogger).is_ok() {
+        log::set_max_level(log::LevelFilter::Trace);
+    }
+}
+
+pub fn captured_logs() -> Vec<String> {
+    CAPTURED_LOGS.lock().unwrap().clone()
+}
+
+/// Serves plain HTTP on a loopback port, answering every request with the provided handler.
+pub fn serve<F>(handler: F) -> MockServer
+where
+    F: Fn(&MockRequest) -> MockResponse + Send + Sync + 'static,
+{
+    start_server("http", None, Arc::new(handler))
+}
+
+/// Serves HTTPS on a loopback port using the 'localhost' certificate from the fixtures directory, presenting the test CA
+/// certificate as part of the chain.
+pub fn serve_tls<F>(handler: F) -> MockServer
+where
+    F: Fn(&MockRequest) -> MockResponse + Send + Sync + 'static,
+{
+    let chain = [include_bytes!("../fixtures/localhost.pem").as_slice(), include_bytes!("../fixtures/ca.pem").as_slice()].concat();
+    let identity = native_tls::Identity::from_pkcs8(&chain, include_bytes!("../fixtures/localhost.key")).unwrap();
+    let acceptor = native_tls::TlsAcceptor::new(identity).unwrap();
+    start_server("https", Some(Arc::new(acceptor)), Arc::new(handler))
+}
+
+pub fn fixture_ca_certificate() -> native_tls::Certificate {
+    native_tls::Certificate::from_pem(include_bytes!("../fixtures/ca.pem")).unwrap()
+}
+
+fn start_server(scheme: &str, acceptor: Option<Arc<native_tls::TlsAcceptor>>, handler: MockHandler) -> MockServer {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let shutdown = Arc::new(AtomicBool::new(false));
+
+    let requests_clone = requests.clone();
+    let shutdown_clone = shutdown.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            if shutdown_clone.load(Ordering::SeqCst) {
+                break;
+            }
+            let Ok(stream) = stream else { continue };
+            let handler = handler.clone();
+            let requests = requests_clone.clone();
+            let acceptor = acceptor.clone();
+            thread::spawn(move || match acceptor {
+                Some(acceptor) => {
+                    if let Ok(tls) = acceptor.accept(stream) {
+                        handle_connection(tls, &handler, &requests);
+                    }
+                }
+                None => handle_connection(stream, &handler, &requests),
+            });
+        }
+    });
+
+    MockServer { url: format!("{}://localhost:{}", scheme, addr.port()), addr, requests, shutdown }
+}
+
+fn handle_connection<S: Read + Write>(mut stream: S, handler: &MockHandler, requests: &Mutex<Vec<MockRequest>>) {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    while !data.windows(4).any(|w| w == b"\r\n\r\n") {
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => return,
+            Ok(n) => data.extend_from_slice(&buf[..n]),
+        }
+    }
+
+    let text = String::from_utf8_lossy(&data);
+    let mut lines = text.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default().to_owned();
+    let path = request_line.next().unwrap_or_default().to_owned();
+    let headers = lines
+        .take_while(|l| !l.is_empty())
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim().to_owned(), v.trim().to_owned()))
+        .collect();
+    let request = MockRequest { method, path, headers };
+    requests.lock().unwrap().push(request.clone());
+
+    let response = handler(&request);
+    let mut head = format!("HTTP/1.1 {} Mock\r\nConnection: close\r\n", response.status);
+    if !response.headers.iter().any(|(k, _)| k.eq_ignore_ascii_case("Content-Length") || k.eq_ignore_ascii_case("Transfer-Encoding")) {
+        head.push_str(&format!("Content-Length: {}\r\n", response.body.len()));
+    }
+    for (k, v) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", k, v));
+    }
+    head.push_str("\r\n");
+    let _ = stream.write_all(head.as_bytes());
+    if request.method != "HEAD" {
+        let _ = stream.write_all(&response.body);
+    }
+    let _ = stream.flush();
+}
@@ -96,11 +96,11 @@ fn check(matches: &ArgMatches) -> Result<()> {
     let channel = matches.get_one::<String>("channel").map(|x| x.to_owned());
 
     info!("Command: Check");
-    info!("    URL: {:?}", url);
+    info!("    URL: {:?}", sources::sanitize_url(url));
     info!("    Allow Downgrade: {:?}", allow_downgrade);
     info!("    Channel: {:?}", channel);
 
-    let options = UpdateOptions { AllowVersionDowngrade: allow_downgrade, ExplicitChannel: channel };
+    let options = UpdateOptions { AllowVersionDowngrade: allow_downgrade, ExplicitChannel: channel, ..Default::default() };
     let updates = if is_http_url(url) {
         let source = sources::HttpSource::new(url);
         let um = UpdateManager::new(source, Some(options))?;
@@ -124,7 +124,7 @@ fn download(matches: &ArgMatches) -> Result<()> {
     let channel = matches.get_one::<String>("channel").map(|x| x.to_owned());
 
     info!("Command: Download");
-    info!("    URL: {:?}", url);
+    info!("    URL: {:?}", sources::sanitize_url(url));
     info!("    Asset Name: {:?}", name);
     info!("    Channel: {:?}", channel);
 
@@ -139,7 +139,7 @@ fn download(matches: &ArgMatches) -> Result<()> {
 }
 
 fn download_generic<T: UpdateSource>(source: T, name: &str, channel: Option<String>) -> Result<()> {
-    let options = UpdateOptions { AllowVersionDowngrade: false, ExplicitChannel: channel };
+    let options = UpdateOptions { AllowVersionDowngrade: false, ExplicitChannel: channel, ..Default::default() };
     let um = UpdateManager::new(source, Some(options))?;
     let feed = um.get_release_feed()?;
     let asset = feed.find(&name).ok_or_else(|| anyhow!("Asset not found in feed: {}", name))?;